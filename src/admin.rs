@@ -0,0 +1,222 @@
+use cosmwasm_std::{
+    log, Api, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult, Storage,
+};
+
+use crate::escrow::MAX_ESCROW_EPOCHS;
+use crate::msg::{ConfigResponse, ConfigUpdate};
+use crate::state::{load, save, ContractStatus, PauseMatrix, RewardSource, State, CONFIG_KEY};
+
+pub fn check_admin(state: &State, env: &Env) -> StdResult<()> {
+    if env.message.sender != state.owner {
+        return Err(StdError::generic_err("This is an admin command. Admin commands can only be run from the admin address"));
+    }
+    Ok(())
+}
+
+// Step one of handing over the contract. The new admin has to call AcceptAdmin before anything changes.
+pub fn try_change_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    // Make sure the address is valid before we hand anything over
+    deps.api.canonical_address(&address)?;
+
+    state.pending_owner = Some(address.clone());
+    save(&mut deps.storage, CONFIG_KEY, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("pending_admin", address)],
+        data: None,
+    })
+}
+
+pub fn try_accept_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut state: State = load(&deps.storage, CONFIG_KEY)?;
+
+    match &state.pending_owner {
+        Some(pending) if pending == &env.message.sender => {}
+        _ => return Err(StdError::generic_err("You have not been nominated as the new admin")),
+    }
+
+    state.owner = env.message.sender.clone();
+    state.pending_owner = None;
+    save(&mut deps.storage, CONFIG_KEY, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("admin", env.message.sender)],
+        data: None,
+    })
+}
+
+pub fn try_update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    update: ConfigUpdate,
+) -> StdResult<HandleResponse> {
+    let mut state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    if let Some(size) = update.max_contents_size {
+        state.config.max_contents_size = size;
+    }
+    if let Some(size) = update.max_multi_size {
+        state.config.max_multi_size = size;
+    }
+    if let Some(size) = update.max_node_list_size {
+        if size == 0 {
+            return Err(StdError::generic_err("max_node_list_size must be greater than 0"));
+        }
+        state.config.max_node_list_size = size;
+    }
+    if let Some(enabled) = update.node_allowlist_enabled {
+        state.config.node_allowlist_enabled = enabled;
+    }
+    if let Some(blocks) = update.heartbeat_interval {
+        state.config.heartbeat_interval = blocks;
+    }
+    if let Some(blocks) = update.score_decay_blocks {
+        state.config.score_decay_blocks = blocks;
+    }
+    if let Some(blocks) = update.challenge_window {
+        state.config.challenge_window = blocks;
+    }
+    if let Some(source) = update.reward_source {
+        state.config.reward_source = source;
+    }
+    if let Some(amount) = update.reward_per_credit {
+        state.config.reward_per_credit = amount;
+    }
+    if let Some(amount) = update.min_node_stake {
+        state.config.min_node_stake = amount;
+    }
+    if let Some(denom) = update.stake_denom {
+        state.config.stake_denom = denom;
+    }
+    if let Some(blocks) = update.unbonding_period {
+        state.config.unbonding_period = blocks;
+    }
+    if let Some(percent) = update.slash_percent {
+        if percent > 100 {
+            return Err(StdError::generic_err("slash_percent can't be more than 100"));
+        }
+        state.config.slash_percent = percent;
+    }
+    if let Some(factor) = update.replication_factor {
+        state.config.replication_factor = factor;
+    }
+    if let Some(score) = update.min_select_score {
+        state.config.min_select_score = score;
+    }
+    if let Some(epochs) = update.escrow_epochs {
+        if epochs == 0 || epochs > MAX_ESCROW_EPOCHS {
+            return Err(StdError::generic_err(format!("escrow_epochs must be between 1 and {}", MAX_ESCROW_EPOCHS)));
+        }
+        state.config.escrow_epochs = epochs;
+    }
+    if let Some(denom) = update.payment_denom {
+        state.config.payment_denom = denom;
+    }
+    if let Some(amount) = update.write_fee {
+        state.config.write_fee = amount;
+    }
+    if let Some(credits) = update.epoch_emission {
+        state.config.epoch_emission = credits;
+    }
+    if let Some(blocks) = update.upload_window {
+        state.config.upload_window = blocks;
+    }
+    if let RewardSource::Native { denom } = &state.config.reward_source {
+        if denom != &state.config.stake_denom {
+            return Err(StdError::generic_err("A native reward source must use the stake denom"));
+        }
+    }
+
+    save(&mut deps.storage, CONFIG_KEY, &state)?;
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_set_contract_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    level: ContractStatus,
+) -> StdResult<HandleResponse> {
+    let mut state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    state.status = level;
+    save(&mut deps.storage, CONFIG_KEY, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("contract_status", format!("{:?}", level))],
+        data: None,
+    })
+}
+
+pub fn try_set_pause<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    file_writes: Option<bool>,
+    permission_changes: Option<bool>,
+    node_registration: Option<bool>,
+    reward_claims: Option<bool>,
+) -> StdResult<HandleResponse> {
+    let mut state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    if let Some(paused) = file_writes {
+        state.pause.file_writes = paused;
+    }
+    if let Some(paused) = permission_changes {
+        state.pause.permission_changes = paused;
+    }
+    if let Some(paused) = node_registration {
+        state.pause.node_registration = paused;
+    }
+    if let Some(paused) = reward_claims {
+        state.pause.reward_claims = paused;
+    }
+
+    save(&mut deps.storage, CONFIG_KEY, &state)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("file_writes", state.pause.file_writes),
+            log("permission_changes", state.pause.permission_changes),
+            log("node_registration", state.pause.node_registration),
+            log("reward_claims", state.pause.reward_claims),
+        ],
+        data: None,
+    })
+}
+
+pub fn query_pause_matrix<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<PauseMatrix> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    Ok(state.pause)
+}
+
+pub fn query_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ConfigResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+
+    Ok(ConfigResponse {
+        admin: state.owner,
+        pending_admin: state.pending_owner,
+        status: state.status,
+        config: state.config,
+    })
+}
@@ -1,36 +1,60 @@
 // use std::io::Stderr;
 use std::vec;
 
+use std::cmp;
+
 use cosmwasm_std::{
-    debug_print, to_binary, Api, Env, Extern, HandleResponse, HumanAddr, Querier, StdError,
+    debug_print, log, to_binary, Api, Binary, Env, Extern, HandleResponse, HumanAddr, Querier, StdError,
     StdResult, Storage,
 };
 use cosmwasm_storage::{bucket, bucket_read};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::msg::{FileResponse, HandleAnswer, WalletInfoResponse, ResponseStatus::Success};
+use crate::challenge::{copy_file_root, release_file_root};
+use crate::content::{content_digest, load_body, release_body, retain_body};
+use crate::placement::{copy_placement, track_file, untrack_file};
+use crate::escrow::{check_write_fee, close_escrow, escrow_payment, move_escrow, open_escrow, split_payment};
+use crate::manifest::{clear_manifest, manifest_digest, move_manifest};
+use crate::namespace_root::{
+    namespace_leaf_count, namespace_path_at, refresh_namespace_entry, remove_namespace_entry,
+    set_namespace_entry,
+};
+use crate::msg::{FileResponse, HandleAnswer, VerifyContentResponse, WalletInfoResponse, ResponseStatus::Success};
 use crate::nodes::write_claim;
 use crate::ordered_set::OrderedSet;
+use crate::path::parent_path;
 use crate::state::{load, write_viewing_key, State, CONFIG_KEY};
 use crate::viewing_key::ViewingKey;
 
 // Bucket namespace list:
 static WALLET_INFO_LOCATION: &[u8] = b"WALLET_INFO";
+// Number of entries directly in each folder, by file_key
+static FOLDER_CHILDREN_LOCATION: &[u8] = b"FOLDER_CHILDREN";
+// Namespaces left behind by ForgetMe that SweepForgotten hasn't finished cleaning up, per address
+static FORGOTTEN_LOCATION: &[u8] = b"FORGOTTEN_NAMESPACES";
+
+// Most entries SweepForgotten cleans up per call
+pub const MAX_SWEEP_PAGE: u64 = 100;
+
+// `next` is the first leaf of the namespace tree that hasn't been cleaned up yet
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ForgottenNamespace {
+    pub namespace: String,
+    pub next: u64,
+}
 
 // HandleMsg::InitAddress
 pub fn try_init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
-    contents: String,
     entropy: String,
 ) -> StdResult<HandleResponse> {
     let ha = deps
         .api
         .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
     let adr = String::from(ha.as_str());
-    let mut path = adr.to_string();
-    path.push('/');
+    let path = adr.to_string();
 
     let namespace = get_namespace(&deps.storage, &adr).unwrap_or(String::from("namespace does not exist!"));
     let already_init = file_exists(&mut deps.storage, &path, &namespace);
@@ -60,7 +84,7 @@ pub fn try_init<S: Storage, A: Api, Q: Querier>(
                 Err(e) => panic!("Bucket Error: {}", e),
             }
 
-            create_file(deps, adr.to_string(), path.clone(), contents);
+            create_folder(deps, adr.to_string(), path.clone());
 
             // Let's create viewing key
             let config: State = load(&mut deps.storage, CONFIG_KEY)?;
@@ -116,6 +140,14 @@ pub fn try_forget_me<S: Storage, A: Api, Q: Querier>(
         bucket_read(WALLET_INFO_LOCATION, &deps.storage).load(adr.as_bytes());
     let mut wallet_info = load_bucket?;
 
+    // The old namespace can't be reached by path any more. Cleaning up after its files can take more than
+    // one transaction, so it is queued for SweepForgotten.
+    if namespace_leaf_count(&deps.storage, &wallet_info.namespace) > 0 {
+        let mut forgotten = load_forgotten(&deps.storage, &adr);
+        forgotten.push(ForgottenNamespace { namespace: wallet_info.namespace.clone(), next: 0 });
+        bucket(FORGOTTEN_LOCATION, &mut deps.storage).save(adr.as_bytes(), &forgotten)?;
+    }
+
     wallet_info.init = false;
     let new_counter = wallet_info.counter + 1;
     wallet_info.counter = new_counter; 
@@ -130,6 +162,58 @@ pub fn try_forget_me<S: Storage, A: Api, Q: Querier>(
     Ok(HandleResponse::default())
 }
 
+fn load_forgotten<S: Storage>(store: &S, address: &str) -> Vec<ForgottenNamespace> {
+    bucket_read(FORGOTTEN_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+// Takes up to `limit` files and folders from the namespaces `address` forgot, oldest first, out of the placement
+// and challenge indexes and closes their escrows, refunding the rest to each uploader. Anyone can call this.
+pub fn try_sweep_forgotten<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+    limit: u64,
+) -> StdResult<HandleResponse> {
+    let mut forgotten = load_forgotten(&deps.storage, address.as_str());
+    if forgotten.is_empty() {
+        return Err(StdError::generic_err("This address has nothing left to clean up"));
+    }
+
+    let mut budget = cmp::min(limit, MAX_SWEEP_PAGE);
+    let mut swept: u64 = 0;
+    while budget > 0 && !forgotten.is_empty() {
+        let entry = &mut forgotten[0];
+        let end = cmp::min(namespace_leaf_count(&deps.storage, &entry.namespace), entry.next + budget);
+        for index in entry.next..end {
+            let key = file_key(&entry.namespace, &namespace_path_at(&deps.storage, &entry.namespace, index)?);
+            untrack_file(&mut deps.storage, &key)?;
+            release_file_root(&mut deps.storage, &key)?;
+            close_escrow(&mut deps.storage, &key, env.block.height)?;
+        }
+        budget -= end - entry.next;
+        swept += end - entry.next;
+        entry.next = end;
+        if end == namespace_leaf_count(&deps.storage, &entry.namespace) {
+            forgotten.remove(0);
+        }
+    }
+
+    if forgotten.is_empty() {
+        bucket::<S, Vec<ForgottenNamespace>>(FORGOTTEN_LOCATION, &mut deps.storage).remove(address.as_str().as_bytes());
+    } else {
+        bucket(FORGOTTEN_LOCATION, &mut deps.storage).save(address.as_str().as_bytes(), &forgotten)?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("swept", swept), log("remaining", forgotten.len())],
+        data: None,
+    })
+}
+
 pub fn try_you_up_bro<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     address: String,
@@ -333,9 +417,20 @@ pub struct WalletInfo {
     pub counter: i32
 }
 
+// What an entry in a namespace is. Files and folders are addressed the same way, so this is the only place the
+// difference is kept.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Node {
+    Folder,
+    File,
+}
+
 // HandleMsg FILE
+// Folders have no contents, so `contents` is always empty for them
 #[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
 pub struct File {
+    node: Node,
     contents: String,
     owner: String,
     public: bool,
@@ -348,6 +443,10 @@ impl File {
         &self.contents
     }
 
+    pub fn is_folder(&self) -> bool {
+        self.node == Node::Folder
+    }
+
     /**
       Please call these before doing anything to files. If you are adding a newly
       created file to a folder, please check that you can write to the folder. If
@@ -473,23 +572,34 @@ pub fn try_move_file<S: Storage, A: Api, Q: Querier>(
         return Err(StdError::GenericErr { msg: "You do not own this file and cannot move it".to_string(), backtrace: None })
     }
 
+    if file_res.is_folder() {
+        if new_path.starts_with(&format!("{}/", old_path)) {
+            return Err(StdError::generic_err("A folder can't be moved into itself"));
+        }
+        check_folder_empty(&deps.storage, &old_path, &namespace)?;
+    }
+
     let duplicated_contents = file_res.contents;
     
     //this was previously try_create_file
-    let new_file = do_create_file(
-        deps,
-        env.message.sender.to_string(),
-        duplicated_contents,
-        new_path,
-        String::from(""),//Nug/Marston: do we need to put something here?
-        String::from(""),//Nug/Marston: do we need to put something here?
-    );
+    let new_file = if file_res.node == Node::Folder {
+        do_create_folder(deps, env.message.sender.to_string(), new_path.clone())
+    } else {
+        do_create_file(
+            deps,
+            env.message.sender.to_string(),
+            duplicated_contents,
+            new_path.clone(),
+            String::from(""),//Nug/Marston: do we need to put something here?
+            String::from(""),//Nug/Marston: do we need to put something here?
+        )
+    };
 
     match new_file {
         Ok(handle_response) => handle_response,
         Err(e) => match e {
-            StdError::GenericErr { msg:_, backtrace:None } => 
-            return Err(StdError::GenericErr { msg: "File move unsuccessful. Not permitted to write to destination folder".to_string(), backtrace: None }),
+            StdError::GenericErr { msg, backtrace:None } => 
+            return Err(StdError::GenericErr { msg: format!("File move unsuccessful. {}", msg), backtrace: None }),
             
             StdError::NotFound { kind:_, backtrace:None } => 
             return Err(StdError::NotFound { kind: "File move unsuccessful. Destination folder does not exist".to_string(), backtrace: None }),
@@ -498,6 +608,16 @@ pub fn try_move_file<S: Storage, A: Api, Q: Querier>(
         }
     };
 
+    // Keep the storage root registered for the file at its new path
+    let new_namespace = get_namespace_from_path(&deps, new_path.clone()).unwrap_or(String::from("namespace not found!"));
+    copy_file_root(&mut deps.storage, &file_key(&namespace, &old_path), &file_key(&new_namespace, &new_path))?;
+    copy_placement(&mut deps.storage, &file_key(&namespace, &old_path), &file_key(&new_namespace, &new_path))?;
+    move_escrow(&mut deps.storage, &file_key(&namespace, &old_path), &file_key(&new_namespace, &new_path), env.block.height)?;
+    move_manifest(&mut deps.storage, &file_key(&namespace, &old_path), &file_key(&new_namespace, &new_path))?;
+    if manifest_digest(&deps.storage, &file_key(&new_namespace, &new_path)).is_some() {
+        refresh_namespace_entry(&mut deps.storage, &new_namespace, &new_path)?;
+    }
+
     let removed = try_remove_file(deps, env, old_path);
     //if we were able to get contents of old_path above, then try_remove_file should always succeed, but I want to keep this here just incase
     match removed {
@@ -517,6 +637,10 @@ pub fn try_move_multi_files<S: Storage, A: Api, Q: Querier>(
     new_path_list: Vec<String>,
 ) -> StdResult<HandleResponse> {
     debug_print!("Attempting to move multiple files");
+    check_multi_size(&deps.storage, old_path_list.len())?;
+    if new_path_list.len() != old_path_list.len() {
+        return Err(StdError::generic_err("old_path_list and new_path_list must be the same length"));
+    }
 
     for i in 0..old_path_list.len() {
         let old_path = &old_path_list[i];
@@ -556,7 +680,14 @@ pub fn try_remove_file<S: Storage, A: Api, Q: Querier>(
     match res {
         Ok(f) => {
             if f.owner == env.message.sender.to_string() {
+                if f.is_folder() {
+                    check_folder_empty(&deps.storage, &path, &namespace)?;
+                }
                 bucket_remove_file(&mut deps.storage, &path, &namespace);
+                release_file_root(&mut deps.storage, &file_key(&namespace, &path))?;
+                untrack_file(&mut deps.storage, &file_key(&namespace, &path))?;
+                close_escrow(&mut deps.storage, &file_key(&namespace, &path), env.block.height)?;
+                clear_manifest(&mut deps.storage, &file_key(&namespace, &path))?;
                 return Ok(HandleResponse::default());
             }
             Err(StdError::GenericErr { msg: "Sorry. You are not authorized to remove this file".to_string(), backtrace: None })
@@ -575,56 +706,92 @@ fn do_create_file<S: Storage, A: Api, Q: Querier>(
     pkey: String,
     skey: String,
 ) -> StdResult<HandleResponse> {
-    let par_path = parent_path(path.to_string());
-
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    if contents.len() as u64 > config.config.max_contents_size {
+        return Err(StdError::generic_err(format!("File contents are larger than the {} byte limit", config.config.max_contents_size)));
+    }
     let namespace = get_namespace_from_path(&deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
-    let res = bucket_load_readonly_file(&deps.storage, &par_path, &namespace);
+    if matches!(load_stored_file(&deps.storage, &path, &namespace)?, Some(stored) if stored.node == Node::Folder) {
+        return Err(StdError::generic_err("A folder with this name already exists"));
+    }
+    check_parent_folder(deps, &ha, &path, &namespace)?;
 
-    match res {
-        Ok(f) => {
-            if f.can_write(ha.to_string()) {
-                // Add new file to bucket
-                create_file(
-                    deps,
-                    ha.to_string(),
-                    path.to_string(),
-                    contents,
-                );
+    // Add new file to bucket
+    create_file(
+        deps,
+        ha.to_string(),
+        path.to_string(),
+        contents,
+    );
 
-                let adr = String::from(&ha);
-                let mut acl = adr;
-                acl.push_str(&pkey);
+    // Moves pass an empty pkey and shouldn't leave a claim code anyone could redeem
+    if !pkey.is_empty() {
+        write_claim(&mut deps.storage, &ha, &pkey, &skey);
+    }
 
-                write_claim(&mut deps.storage, acl, skey);
+    Ok(HandleResponse::default())
+}
 
-                return Ok(HandleResponse::default());
-            }
-            Err(StdError::GenericErr { msg: "Sorry. You are unauthorized to create a file in this folder.".to_string(), backtrace: None })
-        }
-        Err(_e) => {
-            Err(StdError::NotFound { kind: format!("File creation unsuccessful. Parent path: '{}' doesn't exist.", &par_path), backtrace: None })
-        }
+fn do_create_folder<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    ha: String,
+    path: String,
+) -> StdResult<HandleResponse> {
+    let namespace = get_namespace_from_path(&deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    match load_stored_file(&deps.storage, &path, &namespace)? {
+        Some(stored) if stored.node == Node::Folder => return Err(StdError::generic_err("A folder already exists at this path")),
+        Some(_) => return Err(StdError::generic_err("A file with this name already exists")),
+        None => {}
     }
+    check_parent_folder(deps, &ha, &path, &namespace)?;
+
+    create_folder(deps, ha, path);
+
+    Ok(HandleResponse::default())
 }
 
-fn parent_path(mut path: String) -> String {
-    if path.ends_with('/') {
-        path.pop();
+// New entries can only go in a folder the creator can write to. Files never have children.
+fn check_parent_folder<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ha: &str,
+    path: &str,
+    namespace: &String,
+) -> StdResult<()> {
+    let par_path = parent_path(path).unwrap_or_default().to_string();
+    let parent = match bucket_load_readonly_file(&deps.storage, &par_path, namespace) {
+        Ok(parent) => parent,
+        Err(_e) => return Err(StdError::NotFound { kind: format!("File creation unsuccessful. Parent path: '{}' doesn't exist.", &par_path), backtrace: None }),
+    };
+
+    if !parent.is_folder() {
+        return Err(StdError::generic_err(format!("'{}' is a file, so nothing can be created in it", &par_path)));
     }
-    let split = path.split('/');
-    let vec = split.collect::<Vec<&str>>();
+    if !parent.can_write(ha.to_string()) {
+        return Err(StdError::GenericErr { msg: "Sorry. You are unauthorized to create a file in this folder.".to_string(), backtrace: None });
+    }
+    Ok(())
+}
 
-    let mut par_path = String::new();
+fn folder_children<S: Storage>(store: &S, namespace: &str, path: &str) -> u64 {
+    bucket_read(FOLDER_CHILDREN_LOCATION, store)
+        .may_load(file_key(namespace, path).as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(0)
+}
 
-    let mut i = 0;
-    while i < vec.len() - 1 {
-        let s = vec[i];
-        par_path.push_str(s);
-        par_path.push('/');
-        i += 1;
-    }
+// Called when an entry is added to or taken out of the folder at `path`
+fn count_child<S: Storage>(store: &mut S, namespace: &str, path: &str, added: bool) -> StdResult<()> {
+    let count = folder_children(store, namespace, path);
+    let count = if added { count + 1 } else { count.saturating_sub(1) };
+    bucket(FOLDER_CHILDREN_LOCATION, store).save(file_key(namespace, path).as_bytes(), &count)
+}
 
-    par_path
+// Folders are moved and removed one level at a time, so their contents never lose their parent
+fn check_folder_empty<S: Storage>(store: &S, path: &str, namespace: &str) -> StdResult<()> {
+    if folder_children(store, namespace, path) > 0 {
+        return Err(StdError::generic_err("This folder isn't empty. Move or remove what is in it first"));
+    }
+    Ok(())
 }
 
 pub fn try_create_file<S: Storage, A: Api, Q: Querier>(
@@ -634,13 +801,36 @@ pub fn try_create_file<S: Storage, A: Api, Q: Querier>(
     path: String,
     pkey: String,
     skey: String,
+) -> StdResult<HandleResponse> {
+    let ha = deps
+        .api
+        .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let payment = escrow_payment(&deps.storage, &env.message.sent_funds)?;
+    check_write_fee(&deps.storage, payment)?;
+
+    do_create_file(deps, ha.to_string(), contents, path.clone(), pkey, skey)?;
+
+    // Anything sent along pays nodes for keeping the file
+    if payment > 0 {
+        let namespace = get_namespace_from_path(&deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+        open_escrow(&mut deps.storage, &file_key(&namespace, &path), ha.as_str(), payment, env.block.height)?;
+    }
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_create_folder<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
 ) -> StdResult<HandleResponse> {
     let ha = deps
         .api
         .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
 
-    do_create_file(deps, ha.to_string(), contents, path, pkey, skey)
+    do_create_folder(deps, ha.to_string(), path)
 }
+
 pub fn try_create_multi_files<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -653,6 +843,12 @@ pub fn try_create_multi_files<S: Storage, A: Api, Q: Querier>(
         .api
         .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
     debug_print!("Attempting to create multiple files for account: {}", ha);
+    check_multi_size(&deps.storage, contents_list.len())?;
+    let len = contents_list.len();
+    if paths.len() != len || pkeys.len() != len || skeys.len() != len {
+        return Err(StdError::generic_err("contents_list, path_list, pkey_list and skey_list must be the same length"));
+    }
+    let payments = split_payment(&deps.storage, &env.message.sent_funds, len)?;
 
     for i in 0..contents_list.len() {
         let file_contents = contents_list[i].clone();
@@ -664,15 +860,28 @@ pub fn try_create_multi_files<S: Storage, A: Api, Q: Querier>(
             deps,
             ha.to_string(),
             file_contents,
-            path,
+            path.clone(),
             pkey.to_string(),
             skey.to_string(),
         )?;
+
+        if payments[i] > 0 {
+            let namespace = get_namespace_from_path(&deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+            open_escrow(&mut deps.storage, &file_key(&namespace, &path), ha.as_str(), payments[i], env.block.height)?;
+        }
     }
 
     Ok(HandleResponse::default())
 }
 
+pub fn check_multi_size<S: Storage>(store: &S, len: usize) -> StdResult<()> {
+    let config: State = load(store, CONFIG_KEY)?;
+    if len as u64 > config.config.max_multi_size {
+        return Err(StdError::generic_err(format!("Too many files in one request. The limit is {}", config.config.max_multi_size)));
+    }
+    Ok(())
+}
+
 pub fn try_remove_multi_files<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
@@ -682,6 +891,7 @@ pub fn try_remove_multi_files<S: Storage, A: Api, Q: Querier>(
         .api
         .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
     debug_print!("Attempting to remove multiple files for account: {}", ha);
+    check_multi_size(&deps.storage, path_list.len())?;
 
     for i in 0..path_list.len() {
         let path = path_list[i].to_string();
@@ -707,10 +917,106 @@ pub fn create_file<S: Storage, A: Api, Q: Querier>(
 
     let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
     bucket_save_file(&mut deps.storage, &path, file, &namespace);
+
+    reset_file_indexes(&mut deps.storage, &namespace, &path).expect("File Index Error");
+}
+
+// New contents means any storage root, placement confirmations or manifest recorded for an old file at this path
+// no longer apply
+fn reset_file_indexes<S: Storage>(store: &mut S, namespace: &str, path: &str) -> StdResult<()> {
+    let key = file_key(namespace, path);
+    release_file_root(store, &key)?;
+    track_file(store, namespace, path)?;
+    let had_digest = manifest_digest(store, &key).is_some();
+    clear_manifest(store, &key)?;
+    if had_digest {
+        refresh_namespace_entry(store, namespace, path)?;
+    }
+    Ok(())
+}
+
+// Replaces a file's contents but keeps its owner and permissions. Unlike Create, this needs write access to the
+// file itself rather than to its folder.
+pub fn try_update_file<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    contents: String,
+) -> StdResult<HandleResponse> {
+    let signer = deps
+        .api
+        .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    if contents.len() as u64 > config.config.max_contents_size {
+        return Err(StdError::generic_err(format!("File contents are larger than the {} byte limit", config.config.max_contents_size)));
+    }
+
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let mut f = bucket_load_file(&mut deps.storage, &path, &namespace)?;
+    if f.is_folder() {
+        return Err(StdError::generic_err("Folders have no contents to update"));
+    }
+    if !f.can_write(signer.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to update this file"));
+    }
+
+    f.contents = contents;
+    bucket_save_file(&mut deps.storage, &path, f, &namespace);
+    reset_file_indexes(&mut deps.storage, &namespace, &path)?;
+
+    Ok(HandleResponse::default())
+}
+
+// The copy belongs to the signer, who needs to be able to read the source and write to the destination folder.
+// Both paths share one stored body.
+pub fn try_copy_file<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    from_path: String,
+    to_path: String,
+) -> StdResult<HandleResponse> {
+    let signer = deps
+        .api
+        .human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    let namespace = get_namespace_from_path(deps, from_path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let source = bucket_load_readonly_file(&deps.storage, &from_path, &namespace).map_err(|_| StdError::not_found("File"))?;
+    if source.is_folder() {
+        return Err(StdError::generic_err("Only files can be copied"));
+    }
+    if !source.can_read(signer.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to read this file"));
+    }
+    let payment = escrow_payment(&deps.storage, &env.message.sent_funds)?;
+    check_write_fee(&deps.storage, payment)?;
+
+    do_create_file(deps, signer.to_string(), source.contents, to_path.clone(), String::new(), String::new())?;
+
+    if payment > 0 {
+        let namespace = get_namespace_from_path(&deps, to_path.clone()).unwrap_or(String::from("namespace does not exist!"));
+        open_escrow(&mut deps.storage, &file_key(&namespace, &to_path), signer.as_str(), payment, env.block.height)?;
+    }
+
+    Ok(HandleResponse::default())
+}
+
+pub fn create_folder<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    owner: String,
+    path: String,
+) {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    bucket_save_file(&mut deps.storage, &path, make_folder(&owner), &namespace);
+}
+
+// Identifies a file across namespaces for indexes kept outside the namespace buckets
+pub fn file_key(namespace: &str, path: &str) -> String {
+    format!("{}/{}", namespace, path)
 }
 
 pub fn make_file(owner: &str, contents: &str) -> File {
     File {
+        node: Node::File,
         contents: String::from(contents),
         owner: String::from(owner),
         public: false,
@@ -719,30 +1025,133 @@ pub fn make_file(owner: &str, contents: &str) -> File {
     }
 }
 
+// What the namespace buckets actually hold. The contents live in the shared body store, keyed by their hash,
+// so identical files only take up space once.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Debug, Clone)]
+struct StoredFile {
+    node: Node,
+    contents_hash: Option<String>,
+    owner: String,
+    public: bool,
+    allow_read_list: OrderedSet<String>,
+    allow_write_list: OrderedSet<String>,
+}
+
+impl StoredFile {
+    fn into_file<S: Storage>(self, store: &S) -> StdResult<File> {
+        let contents = match &self.contents_hash {
+            Some(hash) => load_body(store, hash)?,
+            None => String::new(),
+        };
+        Ok(File {
+            node: self.node,
+            contents,
+            owner: self.owner,
+            public: self.public,
+            allow_read_list: self.allow_read_list,
+            allow_write_list: self.allow_write_list,
+        })
+    }
+}
+
+fn load_stored_file<S: Storage>(store: &S, path: &String, namespace: &String) -> StdResult<Option<StoredFile>> {
+    bucket_read(namespace.as_bytes(), store).may_load(path.as_bytes())
+}
+
+pub fn make_folder(owner: &str) -> File {
+    File {
+        node: Node::Folder,
+        contents: String::new(),
+        owner: String::from(owner),
+        public: false,
+        allow_read_list: OrderedSet::<String>::new(),
+        allow_write_list: OrderedSet::<String>::new(),
+    }
+}
+
 pub fn bucket_save_file<'a, S: Storage>(store: &'a mut S, path: &String, folder: File, namespace: &String) {
-    let bucket_response = bucket(namespace.as_bytes(), store).save(path.as_bytes(), &folder);
+    let digest = content_digest(&folder.contents);
+    let contents_hash = match folder.node {
+        Node::File => Some(hex::encode(digest.as_slice())),
+        Node::Folder => None,
+    };
+    let old_hash = load_stored_file(store, path, namespace)
+        .expect("Bucket Load Error")
+        .map(|old| old.contents_hash);
+    let is_new = old_hash.is_none();
+
+    // Only a new entry or a change of contents touches the reference counts and the namespace root,
+    // not a permission or owner change
+    if old_hash.as_ref() != Some(&contents_hash) {
+        if let Some(hash) = &contents_hash {
+            retain_body(store, hash, &folder.contents).expect("Body Save Error");
+        }
+        if let Some(Some(old_hash)) = old_hash {
+            release_body(store, &old_hash).expect("Body Release Error");
+        }
+        set_namespace_entry(store, namespace, path, digest.as_slice()).expect("Namespace Root Error");
+    }
+    if is_new {
+        if let Some(parent) = parent_path(path) {
+            count_child(store, namespace, parent, true).expect("Folder Count Error");
+        }
+    }
+
+    let stored = StoredFile {
+        node: folder.node,
+        contents_hash,
+        owner: folder.owner,
+        public: folder.public,
+        allow_read_list: folder.allow_read_list,
+        allow_write_list: folder.allow_write_list,
+    };
+    let bucket_response = bucket(namespace.as_bytes(), store).save(path.as_bytes(), &stored);
     match bucket_response {
         Ok(bucket_response) => bucket_response,
         Err(e) => panic!("Bucket Save Error: {}", e),
     }
 }
 
+// The digest stored when the file was written. A finalized manifest with a digest from its uploader
+// stands in for the (empty) on-chain contents.
+pub fn file_digest<S: Storage>(store: &S, path: &String, namespace: &String) -> StdResult<Binary> {
+    if let Some(digest) = manifest_digest(store, &file_key(namespace, path)) {
+        return Ok(digest);
+    }
+    contents_digest(store, path, namespace)
+}
+
+// SHA-256 of what is stored on chain, which for a manifest or a folder is nothing
+pub fn contents_digest<S: Storage>(store: &S, path: &String, namespace: &String) -> StdResult<Binary> {
+    let stored = load_stored_file(store, path, namespace)?.ok_or_else(|| StdError::not_found("File"))?;
+    let hash = match stored.contents_hash {
+        Some(hash) => hash,
+        None => return Ok(content_digest("")),
+    };
+    hex::decode(&hash)
+        .map(Binary)
+        .map_err(|_| StdError::generic_err("Corrupt contents hash"))
+}
+
 pub fn bucket_remove_file<'a, S: Storage>(store: &'a mut S, path: &String, namespace: &String) {
-    bucket::<S, File>(namespace.as_bytes(), store).remove(path.as_bytes());
+    if let Ok(Some(old)) = load_stored_file(store, path, namespace) {
+        if let Some(hash) = &old.contents_hash {
+            release_body(store, hash).expect("Body Release Error");
+        }
+        remove_namespace_entry(store, namespace, path).expect("Namespace Root Error");
+        if let Some(parent) = parent_path(path) {
+            count_child(store, namespace, parent, false).expect("Folder Count Error");
+        }
+    }
+    bucket::<S, StoredFile>(namespace.as_bytes(), store).remove(path.as_bytes());
 }
 //need to make file_exists use bucket read
 pub fn file_exists<'a, S: Storage>(store: &'a mut S, path: &String, namespace: &String) -> bool {
-    let f: Result<File, StdError> = bucket(namespace.as_bytes(), store).load(path.as_bytes());
-
-    match f {
-        Ok(_file) => true,
-        Err(_error) => false,
-    }
+    matches!(load_stored_file(store, path, namespace), Ok(Some(_)))
 }
 
 pub fn bucket_load_file<'a, S: Storage>(store: &'a mut S, path: &String, namespace: &String) -> StdResult<File> {
-    let f: Result<File, StdError> = bucket(namespace.as_bytes(), store).load(path.as_bytes());
-    match f {
+    match bucket_load_readonly_file(store, path, namespace) {
         Ok(file) => Ok(file),
         Err(_error) => Err(StdError::NotFound { kind: String::from("No file found at this path."), backtrace: None })
     }
@@ -753,7 +1162,8 @@ pub fn bucket_load_readonly_file<'a, S: Storage>(
     path: &String,
     namespace: &String
 ) -> Result<File, StdError> {
-    bucket_read(namespace.as_bytes(), store).load(path.as_bytes())
+    let stored: StoredFile = bucket_read(namespace.as_bytes(), store).load(path.as_bytes())?;
+    stored.into_file(store)
 }
 
 // QueryMsg
@@ -770,7 +1180,8 @@ pub fn query_file<S: Storage, A: Api, Q: Querier>(
     match f {
         Ok(f1) => {
             if f1.can_read(String::from(behalf.as_str())) {
-                return Ok(FileResponse { file: f1 });
+                let digest = file_digest(&deps.storage, &path, &full_namespace)?;
+                return Ok(FileResponse { file: f1, digest });
             }
             Err(StdError::GenericErr { msg: "Sorry bud! Unauthorized to read file.".to_string(), backtrace: None })
         }
@@ -781,6 +1192,22 @@ pub fn query_file<S: Storage, A: Api, Q: Querier>(
     }
 }
 
+// Only readers can check a digest, otherwise anyone could confirm a guess at a private file's contents
+pub fn query_verify_content<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    path: String,
+    behalf: &HumanAddr,
+    digest: Binary,
+) -> StdResult<VerifyContentResponse> {
+    let namespace = get_namespace_from_path(&deps, path.clone()).unwrap_or(String::from("namespace not found!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace).map_err(|_| StdError::not_found("File"))?;
+    if !file.can_read(behalf.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to read this file"));
+    }
+
+    Ok(VerifyContentResponse { matches: file_digest(&deps.storage, &path, &namespace)? == digest })
+}
+
 //This previously returned a wallet with init = false and namespace = "empty", but this is illogical so we will just return a NotFound error.
 pub fn query_wallet_info<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
@@ -0,0 +1,177 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_std::{
+    log, to_binary, Api, Binary, Coin, Env, Extern, HandleResponse, Querier, StdError, StdResult, Storage, Uint128,
+};
+
+use crate::backend::{
+    check_multi_size, file_digest, get_namespace_from_path, try_allow_read, try_allow_write, try_copy_file,
+    try_create_file, try_disallow_read, try_disallow_write, try_move_file, try_remove_file, try_reset_read,
+    try_reset_write, try_update_file,
+};
+use crate::escrow::split_payment;
+use crate::msg::HandleAnswer;
+use crate::path::canonical_path;
+use crate::state::{load, State, CONFIG_KEY};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionChange {
+    AllowRead,
+    DisallowRead,
+    ResetRead,
+    AllowWrite,
+    DisallowWrite,
+    ResetWrite,
+}
+
+// One step of a Batch. Each behaves like the handle of the same name.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOp {
+    Create { path: String, contents: String, pkey: String, skey: String },
+    Update { path: String, contents: String },
+    Remove { path: String },
+    Move { old_path: String, new_path: String },
+    Copy { from_path: String, to_path: String },
+    // `address_list` is ignored by the reset changes
+    SetPermission { path: String, change: PermissionChange, address_list: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOpResult {
+    Created { path: String, digest: Binary },
+    Updated { path: String, digest: Binary },
+    Removed { path: String },
+    Moved { path: String },
+    Copied { path: String, digest: Binary },
+    PermissionSet { path: String },
+}
+
+impl FileOp {
+    pub fn canonicalize_paths(self) -> StdResult<Self> {
+        Ok(match self {
+            FileOp::Create { path, contents, pkey, skey } => FileOp::Create { path: canonical_path(&path)?, contents, pkey, skey },
+            FileOp::Update { path, contents } => FileOp::Update { path: canonical_path(&path)?, contents },
+            FileOp::Remove { path } => FileOp::Remove { path: canonical_path(&path)? },
+            FileOp::Move { old_path, new_path } => FileOp::Move { old_path: canonical_path(&old_path)?, new_path: canonical_path(&new_path)? },
+            FileOp::Copy { from_path, to_path } => FileOp::Copy { from_path: canonical_path(&from_path)?, to_path: canonical_path(&to_path)? },
+            FileOp::SetPermission { path, change, address_list } => {
+                FileOp::SetPermission { path: canonical_path(&path)?, change, address_list }
+            }
+        })
+    }
+
+    pub fn changes_permissions(&self) -> bool {
+        matches!(self, FileOp::SetPermission { .. })
+    }
+
+    fn creates_file(&self) -> bool {
+        matches!(self, FileOp::Create { .. } | FileOp::Copy { .. })
+    }
+}
+
+// Everything that can be checked without running the ops. What depends on earlier ops, like permissions on a file
+// an earlier op created, is checked as each op runs.
+fn validate_ops<S: Storage>(store: &S, ops: &[FileOp]) -> StdResult<()> {
+    if ops.is_empty() {
+        return Err(StdError::generic_err("No operations in the batch"));
+    }
+    check_multi_size(store, ops.len())?;
+
+    let state: State = load(store, CONFIG_KEY)?;
+    for (index, op) in ops.iter().enumerate() {
+        let contents = match op {
+            FileOp::Create { contents, .. } | FileOp::Update { contents, .. } => contents,
+            _ => continue,
+        };
+        if contents.len() as u64 > state.config.max_contents_size {
+            return Err(StdError::generic_err(format!(
+                "Operation {}: file contents are larger than the {} byte limit",
+                index, state.config.max_contents_size
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn digest_at<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, path: &str) -> StdResult<Binary> {
+    let namespace = get_namespace_from_path(deps, path.to_string())?;
+    file_digest(&deps.storage, &path.to_string(), &namespace)
+}
+
+fn apply_op<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    op: FileOp,
+) -> StdResult<FileOpResult> {
+    Ok(match op {
+        FileOp::Create { path, contents, pkey, skey } => {
+            try_create_file(deps, env.clone(), contents, path.clone(), pkey, skey)?;
+            FileOpResult::Created { digest: digest_at(deps, &path)?, path }
+        }
+        FileOp::Update { path, contents } => {
+            try_update_file(deps, env.clone(), path.clone(), contents)?;
+            FileOpResult::Updated { digest: digest_at(deps, &path)?, path }
+        }
+        FileOp::Remove { path } => {
+            try_remove_file(deps, env.clone(), path.clone())?;
+            FileOpResult::Removed { path }
+        }
+        FileOp::Move { old_path, new_path } => {
+            try_move_file(deps, env.clone(), old_path, new_path.clone())?;
+            FileOpResult::Moved { path: new_path }
+        }
+        FileOp::Copy { from_path, to_path } => {
+            try_copy_file(deps, env.clone(), from_path, to_path.clone())?;
+            FileOpResult::Copied { digest: digest_at(deps, &to_path)?, path: to_path }
+        }
+        FileOp::SetPermission { path, change, address_list } => {
+            match change {
+                PermissionChange::AllowRead => try_allow_read(deps, env.clone(), path.clone(), address_list)?,
+                PermissionChange::DisallowRead => try_disallow_read(deps, env.clone(), path.clone(), address_list)?,
+                PermissionChange::ResetRead => try_reset_read(deps, env.clone(), path.clone())?,
+                PermissionChange::AllowWrite => try_allow_write(deps, env.clone(), path.clone(), address_list)?,
+                PermissionChange::DisallowWrite => try_disallow_write(deps, env.clone(), path.clone(), address_list)?,
+                PermissionChange::ResetWrite => try_reset_write(deps, env.clone(), path.clone())?,
+            };
+            FileOpResult::PermissionSet { path }
+        }
+    })
+}
+
+// Runs the ops in order. If any of them fails the whole handle fails, and the chain discards everything the
+// earlier ops wrote, so a batch is applied completely or not at all.
+pub fn try_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    ops: Vec<FileOp>,
+) -> StdResult<HandleResponse> {
+    validate_ops(&deps.storage, &ops)?;
+    // Funds sent along are shared out over the ops that create files, and each of them runs as if it had been
+    // sent its own share
+    let creates = ops.iter().filter(|op| op.creates_file()).count();
+    let mut payments = split_payment(&deps.storage, &env.message.sent_funds, creates)?.into_iter();
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+
+    let mut results = Vec::with_capacity(ops.len());
+    for (index, op) in ops.into_iter().enumerate() {
+        let mut op_env = env.clone();
+        let payment = if op.creates_file() { payments.next().unwrap_or(0) } else { 0 };
+        op_env.message.sent_funds = if payment > 0 {
+            vec![Coin { denom: state.config.payment_denom.clone(), amount: Uint128(payment) }]
+        } else {
+            vec![]
+        };
+        let result = apply_op(deps, &op_env, op)
+            .map_err(|e| StdError::generic_err(format!("Operation {} failed: {}", index, e)))?;
+        results.push(result);
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("operations", results.len())],
+        data: Some(to_binary(&HandleAnswer::Batch { results })?),
+    })
+}
@@ -0,0 +1,403 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{log, to_binary, Api, Binary, Env, Extern, HandleResponse, Querier, StdError, StdResult, Storage, Uint128};
+use secret_toolkit::crypto::{sha_256, Prng};
+
+use crate::backend::{bucket_load_readonly_file, file_key, get_namespace_from_path};
+use crate::endpoint::normalize_endpoint;
+use crate::epoch::{epoch_at, record_file_proof, record_proof};
+use crate::merkle::{hash_leaf, verify_proof};
+use crate::msg::HandleAnswer;
+use crate::nodes::{load_node_data, load_node_owner, node_exists};
+use crate::placement::{add_replica, check_replica, file_key_of_id, node_file_at, node_file_count, track_file};
+use crate::reputation::adjust_score;
+use crate::staking::slash_stake;
+use crate::state::{load, State, CONFIG_KEY};
+use crate::utils::random_u64;
+
+static STORAGE_ROOT_LOCATION: &[u8] = b"STORAGE_ROOTS";
+static FILE_ROOT_LOCATION: &[u8] = b"FILE_ROOTS";
+static CHALLENGE_LOCATION: &[u8] = b"CHALLENGES";
+static LAST_REQUEST_LOCATION: &[u8] = b"CHALLENGE_REQUESTS";
+
+pub const CHALLENGE_BONUS: u32 = 10;
+pub const CHALLENGE_PENALTY: u32 = 50;
+// Files drawn for a challenge before giving up on finding one that still has a storage root
+pub const MAX_CHALLENGE_DRAWS: u64 = 8;
+
+// One entry per distinct Merkle root. Several paths can point at the same root, e.g. after a move.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct StorageRoot {
+    merkle_root: Vec<u8>,
+    chunk_count: u64,
+    references: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub root: String,
+    pub chunk_index: u64,
+    pub chunk_count: u64,
+    pub nonce: Binary,
+    pub deadline: u64,
+}
+
+// What is stored for a node's open challenge. The file key stays internal so the public challenge
+// doesn't reveal which files a node holds. A confirmation challenge comes from ConfirmStorage and only
+// decides whether the node becomes a replica of the file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OpenChallenge {
+    challenge: Challenge,
+    file: String,
+    confirmation: bool,
+}
+
+fn load_root<S: Storage>(store: &S, root_id: &str) -> StdResult<StorageRoot> {
+    bucket_read(STORAGE_ROOT_LOCATION, store).load(root_id.as_bytes())
+}
+
+pub fn file_root_id<S: Storage>(store: &S, key: &str) -> Option<String> {
+    bucket_read(FILE_ROOT_LOCATION, store).may_load(key.as_bytes()).unwrap_or(None)
+}
+
+fn retain_root<S: Storage>(store: &mut S, key: &str, root_id: &str, merkle_root: Vec<u8>, chunk_count: u64) -> StdResult<()> {
+    let existing: Option<StorageRoot> = bucket_read(STORAGE_ROOT_LOCATION, store).may_load(root_id.as_bytes())?;
+    let root = match existing {
+        Some(mut root) => {
+            if root.chunk_count != chunk_count {
+                return Err(StdError::generic_err(format!(
+                    "Root {} is already registered with {} chunks",
+                    root_id, root.chunk_count
+                )));
+            }
+            root.references += 1;
+            root
+        }
+        None => StorageRoot { merkle_root, chunk_count, references: 1 },
+    };
+    bucket(STORAGE_ROOT_LOCATION, store).save(root_id.as_bytes(), &root)?;
+    bucket(FILE_ROOT_LOCATION, store).save(key.as_bytes(), &root_id.to_string())
+}
+
+// Drops the file's reference to its root, and the root itself once nothing points at it
+pub fn release_file_root<S: Storage>(store: &mut S, key: &str) -> StdResult<()> {
+    let root_id = match file_root_id(store, key) {
+        Some(root_id) => root_id,
+        None => return Ok(()),
+    };
+    bucket::<S, String>(FILE_ROOT_LOCATION, store).remove(key.as_bytes());
+
+    let mut root = load_root(store, &root_id)?;
+    root.references -= 1;
+    if root.references > 0 {
+        return bucket(STORAGE_ROOT_LOCATION, store).save(root_id.as_bytes(), &root);
+    }
+    bucket::<S, StorageRoot>(STORAGE_ROOT_LOCATION, store).remove(root_id.as_bytes());
+
+    Ok(())
+}
+
+// Used when a file moves so the new path keeps the root registered for the old one
+pub fn copy_file_root<S: Storage>(store: &mut S, from_key: &str, to_key: &str) -> StdResult<()> {
+    if let Some(root_id) = file_root_id(store, from_key) {
+        let root = load_root(store, &root_id)?;
+        release_file_root(store, to_key)?;
+        retain_root(store, to_key, &root_id, root.merkle_root, root.chunk_count)?;
+    }
+    Ok(())
+}
+
+pub fn try_register_storage_root<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    merkle_root: Binary,
+    chunk_count: u64,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    if merkle_root.as_slice().len() != 32 {
+        return Err(StdError::generic_err("Merkle root must be 32 bytes"));
+    }
+    if chunk_count == 0 {
+        return Err(StdError::generic_err("chunk_count must be greater than 0"));
+    }
+
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace)
+        .map_err(|_| StdError::not_found("File"))?;
+    if !file.can_write(signer.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to register a storage root for this file"));
+    }
+    if file.is_folder() {
+        return Err(StdError::generic_err("Folders can't have a storage root"));
+    }
+
+    let key = file_key(&namespace, &path);
+    let root_id = hex::encode(merkle_root.as_slice());
+    if file_root_id(&deps.storage, &key).as_deref() != Some(root_id.as_str()) {
+        release_file_root(&mut deps.storage, &key)?;
+        retain_root(&mut deps.storage, &key, &root_id, merkle_root.as_slice().to_vec(), chunk_count)?;
+        // Nodes confirmed the old root, so they have to confirm the new one
+        track_file(&mut deps.storage, &namespace, &path)?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("root", root_id)],
+        data: None,
+    })
+}
+
+fn signer_node_address<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<(String, String)> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let ip = load_node_owner(&deps.storage, signer.as_str())
+        .ok_or_else(|| StdError::not_found("No node is registered to this address"))?;
+    Ok((signer.to_string(), ip))
+}
+
+fn load_challenge<S: Storage>(store: &S, address: &str) -> StdResult<Option<OpenChallenge>> {
+    bucket_read(CHALLENGE_LOCATION, store).may_load(address.as_bytes())
+}
+
+fn check_no_open_challenge<S: Storage>(store: &S, env: &Env, address: &str) -> StdResult<()> {
+    if let Some(open) = load_challenge(store, address)? {
+        if env.block.height <= open.challenge.deadline {
+            return Err(StdError::generic_err("Answer your open challenge before requesting another"));
+        }
+        return Err(StdError::generic_err("Your last challenge expired. It has to be expired with ExpireChallenge first"));
+    }
+    Ok(())
+}
+
+fn challenge_rng(state: &State, env: &Env, address: &str) -> Prng {
+    let mut entropy = Vec::new();
+    entropy.extend_from_slice(&env.block.height.to_be_bytes());
+    entropy.extend_from_slice(&env.block.time.to_be_bytes());
+    entropy.extend_from_slice(address.as_bytes());
+    Prng::new(&state.prng_seed, &entropy)
+}
+
+// Picks a chunk of the root and a nonce, and opens the challenge for `address`
+fn open_challenge<S: Storage>(
+    store: &mut S,
+    rng: &mut Prng,
+    address: &str,
+    file: String,
+    root_id: String,
+    confirmation: bool,
+    deadline: u64,
+) -> StdResult<Challenge> {
+    let root = load_root(store, &root_id)?;
+    let challenge = Challenge {
+        root: root_id,
+        chunk_index: random_u64(rng) % root.chunk_count,
+        chunk_count: root.chunk_count,
+        nonce: Binary(rng.rand_bytes().to_vec()),
+        deadline,
+    };
+    bucket(CHALLENGE_LOCATION, store).save(address.as_bytes(), &OpenChallenge { challenge: challenge.clone(), file, confirmation })?;
+    Ok(challenge)
+}
+
+// Draws one of the files the node confirmed, a chunk of its root and a nonce from the contract seed and the
+// current block. A node gets at most one challenge per challenge_window blocks.
+pub fn try_request_challenge<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let (address, _ip) = signer_node_address(deps, &env)?;
+    check_no_open_challenge(&deps.storage, &env, &address)?;
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let last_request: Option<u64> = bucket_read(LAST_REQUEST_LOCATION, &deps.storage).may_load(address.as_bytes())?;
+    if let Some(last_request) = last_request {
+        if env.block.height < last_request + state.config.challenge_window {
+            return Err(StdError::generic_err(format!(
+                "A node can request one challenge every {} blocks",
+                state.config.challenge_window
+            )));
+        }
+    }
+
+    // Drawn by position, so the cost doesn't grow with the number of files the node holds
+    let count = node_file_count(&deps.storage, &address);
+    let mut rng = challenge_rng(&state, &env, &address);
+    let mut drawn = None;
+    if count > 0 {
+        for _ in 0..MAX_CHALLENGE_DRAWS {
+            let file = node_file_at(&deps.storage, &address, random_u64(&mut rng) % count)?;
+            if let Some(root_id) = file_root_id(&deps.storage, &file) {
+                drawn = Some((file, root_id));
+                break;
+            }
+        }
+    }
+    let (file, root_id) = drawn.ok_or_else(|| StdError::generic_err("This node holds no files with a registered storage root"))?;
+    let deadline = env.block.height + state.config.challenge_window;
+    let challenge = open_challenge(&mut deps.storage, &mut rng, &address, file, root_id, false, deadline)?;
+    bucket(LAST_REQUEST_LOCATION, &mut deps.storage).save(address.as_bytes(), &env.block.height)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Challenge { challenge })?),
+    })
+}
+
+// `file` is the id from GetPlacement or GetUnderReplicated. The node gets a challenge on the file's root, and
+// becomes a replica once it answers it correctly. Failing or missing it costs nothing, the node just isn't added.
+pub fn try_confirm_storage<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    file: String,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let address = signer.to_string();
+    if load_node_owner(&deps.storage, &address).is_none() {
+        return Err(StdError::generic_err("Only registered nodes can confirm storage"));
+    }
+    check_no_open_challenge(&deps.storage, &env, &address)?;
+
+    let key = file_key_of_id(&deps.storage, &file)?;
+    check_replica(&deps.storage, &key, &address)?;
+    let root_id = file_root_id(&deps.storage, &key)
+        .ok_or_else(|| StdError::generic_err("This file has no storage root to confirm against"))?;
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let mut rng = challenge_rng(&state, &env, &address);
+    let deadline = env.block.height + state.config.challenge_window;
+    let challenge = open_challenge(&mut deps.storage, &mut rng, &address, key, root_id, true, deadline)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::Challenge { challenge })?),
+    })
+}
+
+// `response` must be sha256(chunk || nonce) and `proof` must tie sha256(0x00 || chunk) to the challenged root.
+// A wrong answer is recorded rather than returned as an error, otherwise the penalty would be rolled back.
+pub fn try_answer_challenge<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    chunk: Binary,
+    proof: Vec<Binary>,
+    response: Binary,
+) -> StdResult<HandleResponse> {
+    let (address, ip) = signer_node_address(deps, &env)?;
+
+    let open = load_challenge(&deps.storage, &address)?
+        .ok_or_else(|| StdError::not_found("Open challenge"))?;
+    let challenge = open.challenge;
+    bucket::<S, OpenChallenge>(CHALLENGE_LOCATION, &mut deps.storage).remove(address.as_bytes());
+
+    let mut expected = chunk.as_slice().to_vec();
+    expected.extend_from_slice(challenge.nonce.as_slice());
+    let proof: Vec<Vec<u8>> = proof.iter().map(|p| p.as_slice().to_vec()).collect();
+
+    let passed = env.block.height <= challenge.deadline
+        && sha_256(&expected)[..] == response.as_slice()[..]
+        && match load_root(&deps.storage, &challenge.root) {
+            Ok(root) => verify_proof(&root.merkle_root, &hash_leaf(chunk.as_slice()), challenge.chunk_index, root.chunk_count, &proof),
+            // The file was deleted after the challenge went out, so there is nothing left to prove
+            Err(_) => false,
+        };
+
+    if open.confirmation {
+        // The file may have filled up or been rewritten since, in which case the pass doesn't count
+        let replicas = if passed && file_root_id(&deps.storage, &open.file).as_deref() == Some(challenge.root.as_str()) {
+            add_replica(&mut deps.storage, &open.file, &address).ok()
+        } else {
+            None
+        };
+        return Ok(HandleResponse {
+            messages: vec![],
+            log: vec![
+                log("result", if replicas.is_some() { "confirmed" } else { "failed" }),
+                log("replicas", replicas.unwrap_or(0)),
+            ],
+            data: None,
+        });
+    }
+
+    // Passing earns no credits by itself. It counts towards the epoch's DistributeEpoch emission instead.
+    let (score, slashed) = if passed {
+        let score = adjust_score(&mut deps.storage, &ip, env.block.height, CHALLENGE_BONUS as i64, "challenge_passed")?;
+        let epoch = epoch_at(env.block.height);
+        record_proof(&mut deps.storage, &address, epoch)?;
+        // Only counts towards the file's escrow if the file still has the root that was challenged
+        if file_root_id(&deps.storage, &open.file).as_deref() == Some(challenge.root.as_str()) {
+            record_file_proof(&mut deps.storage, epoch, &open.file, &address)?;
+        }
+        (score, Uint128(0))
+    } else {
+        let state: State = load(&deps.storage, CONFIG_KEY)?;
+        let score = adjust_score(&mut deps.storage, &ip, env.block.height, -(CHALLENGE_PENALTY as i64), "challenge_failed")?;
+        (score, slash_stake(&mut deps.storage, &address, state.config.slash_percent, env.block.height)?)
+    };
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("result", if passed { "passed" } else { "failed" }),
+            log("score", score),
+            log("slashed", slashed),
+        ],
+        data: None,
+    })
+}
+
+// Anyone can close out a challenge the node let run past its deadline
+pub fn try_expire_challenge<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    ip: String,
+) -> StdResult<HandleResponse> {
+    let ip = normalize_endpoint(&ip)?;
+    if !node_exists(&deps.storage, &ip) {
+        return Err(StdError::not_found(format!("Node {}", ip)));
+    }
+    let node = load_node_data(&deps.storage, ip.clone());
+    let address = node.get_secret_address().to_string();
+
+    let open = load_challenge(&deps.storage, &address)?
+        .ok_or_else(|| StdError::not_found("Open challenge"))?;
+    if env.block.height <= open.challenge.deadline {
+        return Err(StdError::generic_err("This challenge can still be answered"));
+    }
+    bucket::<S, OpenChallenge>(CHALLENGE_LOCATION, &mut deps.storage).remove(address.as_bytes());
+    if open.confirmation {
+        return Ok(HandleResponse {
+            messages: vec![],
+            log: vec![log("result", "expired")],
+            data: None,
+        });
+    }
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let score = adjust_score(&mut deps.storage, &ip, env.block.height, -(CHALLENGE_PENALTY as i64), "challenge_expired")?;
+    let slashed = slash_stake(&mut deps.storage, &address, state.config.slash_percent, env.block.height)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("result", "expired"), log("score", score), log("slashed", slashed)],
+        data: None,
+    })
+}
+
+pub fn query_challenge<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ip: String,
+) -> StdResult<Option<Challenge>> {
+    let ip = normalize_endpoint(&ip)?;
+    if !node_exists(&deps.storage, &ip) {
+        return Err(StdError::not_found(format!("Node {}", ip)));
+    }
+    let node = load_node_data(&deps.storage, ip);
+    Ok(load_challenge(&deps.storage, node.get_secret_address())?.map(|open| open.challenge))
+}
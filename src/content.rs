@@ -0,0 +1,65 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{Binary, StdError, StdResult, Storage};
+
+static BODY_LOCATION: &[u8] = b"BODIES";
+
+// File contents, stored once however many paths hold them. Keyed by the hex SHA-256 of the contents.
+// There is deliberately no public lookup by hash, since that would tell anyone whether some contents are stored.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+struct Body {
+    contents: String,
+    references: u64,
+}
+
+// The SHA-256 digest clients check data against
+pub fn content_digest(contents: &str) -> Binary {
+    Binary(Sha256::digest(contents.as_bytes()).to_vec())
+}
+
+pub fn content_hash(contents: &str) -> String {
+    hex::encode(content_digest(contents).as_slice())
+}
+
+fn load_entry<S: Storage>(store: &S, hash: &str) -> StdResult<Option<Body>> {
+    bucket_read(BODY_LOCATION, store).may_load(hash.as_bytes())
+}
+
+pub fn load_body<S: Storage>(store: &S, hash: &str) -> StdResult<String> {
+    load_entry(store, hash)?
+        .map(|body| body.contents)
+        .ok_or_else(|| StdError::not_found(format!("Contents {}", hash)))
+}
+
+pub fn body_references<S: Storage>(store: &S, hash: &str) -> u64 {
+    load_entry(store, hash).unwrap_or(None).map_or(0, |body| body.references)
+}
+
+// Adds a reference to the body for `contents`, storing it first if nothing else holds it yet
+pub fn retain_body<S: Storage>(store: &mut S, hash: &str, contents: &str) -> StdResult<()> {
+    let body = match load_entry(store, hash)? {
+        Some(mut body) => {
+            body.references += 1;
+            body
+        }
+        None => Body { contents: contents.to_string(), references: 1 },
+    };
+    bucket(BODY_LOCATION, store).save(hash.as_bytes(), &body)
+}
+
+// Drops a reference, and the body itself once nothing points at it
+pub fn release_body<S: Storage>(store: &mut S, hash: &str) -> StdResult<()> {
+    let mut body = match load_entry(store, hash)? {
+        Some(body) => body,
+        None => return Ok(()),
+    };
+
+    body.references -= 1;
+    if body.references == 0 {
+        bucket::<S, Body>(BODY_LOCATION, store).remove(hash.as_bytes());
+        return Ok(());
+    }
+    bucket(BODY_LOCATION, store).save(hash.as_bytes(), &body)
+}
@@ -3,15 +3,29 @@
 
 // use std::ptr::null;
 
-use cosmwasm_std::{debug_print, to_binary, Api, Binary, Env, Extern, HandleResponse, InitResponse, Querier, StdResult, Storage, QueryResult, StdError};
+use cosmwasm_std::{debug_print, log, to_binary, Api, Binary, Env, Extern, HandleResponse, InitResponse, Querier, StdResult, Storage, QueryResult, StdError};
 use secret_toolkit::crypto::sha_256;
 use std::cmp;
 
-use crate::msg::{HandleMsg, InitMsg, QueryMsg};
-use crate::state::{ State, CONFIG_KEY, save, read_viewing_key};
-use crate::backend::{try_create_viewing_key, try_allow_write, try_disallow_write, try_allow_read, try_disallow_read, query_file, try_create_file, try_init, try_remove_multi_files, try_remove_file, try_move_file, try_create_multi_files, try_reset_read, try_reset_write, try_you_up_bro, query_wallet_info, try_forget_me, try_move_multi_files, try_change_owner};
+use crate::msg::{HandleMsg, InitMsg, QueryMsg, NodeInfo, NodeListResponse, FindNodesResponse};
+use crate::state::{ State, Config, ContractStatus, Operation, PauseMatrix, CONFIG_KEY, save, load, read_viewing_key};
+use crate::backend::{try_create_viewing_key, try_allow_write, try_disallow_write, try_allow_read, try_disallow_read, query_file, try_create_file, try_create_folder, try_init, try_remove_multi_files, try_remove_file, try_move_file, try_create_multi_files, try_reset_read, try_reset_write, try_you_up_bro, query_wallet_info, query_verify_content, try_forget_me, try_sweep_forgotten, try_move_multi_files, try_change_owner};
 use crate::viewing_key::VIEWING_KEY_SIZE;
-use crate::nodes::{pub_query_coins, claim, push_node, get_node, get_node_size, set_node_size};
+use crate::nodes::{pub_query_coins, claim, try_bind_claim_code, push_node, get_node, get_node_size, set_node_size, get_top_nodes, node_exists, node_at_host, load_node_owner, is_node_allowed, try_update_node_allowlist, try_update_node, try_update_node_info, try_remove_node, find_nodes, node_info, node_rank, load_node_data, MAX_NODE_PAGE};
+use crate::reputation::{start_tracking, try_heartbeat, try_report_failure, try_decay_scores, query_score_history};
+use crate::challenge::{try_register_storage_root, try_confirm_storage, try_request_challenge, try_answer_challenge, try_expire_challenge, query_challenge};
+use crate::rewards::{try_fund_reward_pool, try_withdraw_rewards, try_distribute_epoch, query_reward_pool, query_epoch_payouts, NEXT_EPOCH_KEY};
+use crate::epoch::epoch_at;
+use crate::staking::{bond_stake, try_withdraw_stake, try_slash_node, try_withdraw_slashed_stake, query_stake};
+use crate::placement::{query_placement, query_under_replicated};
+use crate::selection::query_select_nodes;
+use crate::manifest::{try_create_manifest, try_append_chunks, try_finalize_manifest, query_manifest};
+use crate::upload::{try_begin_upload, try_upload_chunk, try_commit_upload, try_abort_upload, query_upload_session};
+use crate::namespace_root::{try_record_namespace_root, query_namespace_root, query_namespace_snapshot, query_namespace_proof};
+use crate::batch::{try_batch, FileOp};
+use crate::escrow::{try_settle_escrow, try_withdraw_escrow, query_escrow, query_escrow_balance};
+use crate::endpoint::{normalize_endpoint, NodeEndpoint};
+use crate::admin::{try_change_admin, try_accept_admin, try_update_config, try_set_contract_status, try_set_pause, query_config, query_pause_matrix};
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
@@ -23,10 +37,15 @@ pub fn init<S: Storage, A: Api, Q: Querier>(
 
     let config = State {
         owner: ha,
+        pending_owner: None,
         prng_seed: sha_256(base64::encode(msg.prng_seed).as_bytes()).to_vec(), 
+        status: ContractStatus::Normal,
+        pause: PauseMatrix::default(),
+        config: Config::default(),
     };
 
     set_node_size(&mut deps.storage, 0);
+    save(&mut deps.storage, NEXT_EPOCH_KEY, &epoch_at(env.block.height))?;
 
     debug_print!("Contract was initialized by {}", env.message.sender);
 
@@ -39,14 +58,31 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_status(state.status, &msg)?;
+    if let Some(operation) = operation_of(&msg) {
+        if state.pause.is_paused(operation) {
+            return Err(StdError::generic_err(format!("{:?} operations are paused by the admin", operation)));
+        }
+    }
+    // A batch counts as a file write, but its permission changes are held to that pause as well
+    if let HandleMsg::Batch { ops } = &msg {
+        if ops.iter().any(FileOp::changes_permissions) && state.pause.is_paused(Operation::PermissionChanges) {
+            return Err(StdError::generic_err(format!("{:?} operations are paused by the admin", Operation::PermissionChanges)));
+        }
+    }
+    let msg = msg.canonicalize_paths()?;
+
     match msg {
-        HandleMsg::InitAddress { contents, entropy } => try_init(deps, env, contents, entropy),
+        HandleMsg::InitAddress { entropy, .. } => try_init(deps, env, entropy),
         HandleMsg::Create { contents, path , pkey, skey} => try_create_file(deps, env, contents, path, pkey, skey),
+        HandleMsg::CreateFolder { path } => try_create_folder(deps, env, path),
         HandleMsg::CreateMulti { contents_list, path_list , pkey_list, skey_list} => try_create_multi_files(deps, env, contents_list, path_list, pkey_list, skey_list),
         HandleMsg::Remove {  path } => try_remove_file(deps, env, path),
         HandleMsg::RemoveMulti {  path_list } => try_remove_multi_files(deps, env, path_list),
         HandleMsg::MoveMulti { old_path_list, new_path_list } => try_move_multi_files(deps, env, old_path_list, new_path_list),
         HandleMsg::Move { old_path, new_path } => try_move_file(deps, env, old_path, new_path),
+        HandleMsg::Batch { ops } => try_batch(deps, env, ops),
         HandleMsg::CreateViewingKey { entropy, .. } => try_create_viewing_key(deps, env, entropy),
         HandleMsg::AllowRead { path, address_list } => try_allow_read(deps, env, path, address_list),
         HandleMsg::DisallowRead { path, address_list } => try_disallow_read(deps, env, path, address_list),
@@ -54,10 +90,118 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
         HandleMsg::AllowWrite { path, address_list } => try_allow_write(deps, env, path, address_list),
         HandleMsg::DisallowWrite { path, address_list } => try_disallow_write(deps, env, path, address_list),
         HandleMsg::ResetWrite { path } => try_reset_write(deps, env, path),
-        HandleMsg::InitNode {ip, address} => try_init_node(deps, ip, address),
-        HandleMsg::ClaimReward {path, key, address} => claim(deps, path, key, address),
+        HandleMsg::InitNode {ip, address} => try_init_node(deps, env, ip, address),
+        HandleMsg::UpdateNode { new_ip } => try_update_node(deps, env, new_ip),
+        HandleMsg::UpdateNodeInfo { profile } => try_update_node_info(deps, env, profile),
+        HandleMsg::RemoveNode { } => try_remove_node(deps, env),
+        HandleMsg::WithdrawStake { } => try_withdraw_stake(deps, env),
+        HandleMsg::NodeHeartbeat { } => try_heartbeat(deps, env),
+        HandleMsg::ConfirmStorage { file } => try_confirm_storage(deps, env, file),
+        HandleMsg::ReportNodeFailure { ip, path } => try_report_failure(deps, env, ip, path),
+        HandleMsg::DecayScores { start, limit } => try_decay_scores(deps, env, start, limit),
+        HandleMsg::RegisterStorageRoot { path, merkle_root, chunk_count } => try_register_storage_root(deps, env, path, merkle_root, chunk_count),
+        HandleMsg::RequestChallenge { } => try_request_challenge(deps, env),
+        HandleMsg::AnswerChallenge { chunk, proof, response } => try_answer_challenge(deps, env, chunk, proof, response),
+        HandleMsg::ExpireChallenge { ip } => try_expire_challenge(deps, env, ip),
+        HandleMsg::ClaimReward {path, key, address} => claim(deps, env, path, key, address),
+        HandleMsg::BindClaimCode { path, node } => try_bind_claim_code(deps, env, path, node),
+        HandleMsg::WithdrawRewards { amount } => try_withdraw_rewards(deps, env, amount),
+        HandleMsg::SettleEscrow { path } => try_settle_escrow(deps, env, path),
+        HandleMsg::WithdrawEscrow { } => try_withdraw_escrow(deps, env),
+        HandleMsg::DistributeEpoch { limit } => try_distribute_epoch(deps, env, limit),
+        HandleMsg::CreateManifest { path, pkey, skey } => try_create_manifest(deps, env, path, pkey, skey),
+        HandleMsg::AppendChunks { path, chunks } => try_append_chunks(deps, env, path, chunks),
+        HandleMsg::FinalizeManifest { path, chunk_count, total_size, digest } => try_finalize_manifest(deps, env, path, chunk_count, total_size, digest),
+        HandleMsg::BeginUpload { path, total_size, chunk_count } => try_begin_upload(deps, env, path, total_size, chunk_count),
+        HandleMsg::UploadChunk { session, index, data } => try_upload_chunk(deps, env, session, index, data),
+        HandleMsg::CommitUpload { session, pkey, skey } => try_commit_upload(deps, env, session, pkey, skey),
+        HandleMsg::AbortUpload { session } => try_abort_upload(deps, env, session),
         HandleMsg::ForgetMe { .. } => try_forget_me(deps, env),
-        HandleMsg::ChangeOwner { path, new_owner } => try_change_owner(deps, env, path, new_owner)
+        HandleMsg::SweepForgotten { address, limit } => try_sweep_forgotten(deps, env, address, limit),
+        HandleMsg::RecordNamespaceRoot { } => try_record_namespace_root(deps, env),
+        HandleMsg::ChangeOwner { path, new_owner } => try_change_owner(deps, env, path, new_owner),
+        HandleMsg::ChangeAdmin { address } => try_change_admin(deps, env, address),
+        HandleMsg::AcceptAdmin { } => try_accept_admin(deps, env),
+        HandleMsg::UpdateConfig { config } => try_update_config(deps, env, config),
+        HandleMsg::SetContractStatus { level } => try_set_contract_status(deps, env, level),
+        HandleMsg::SetPause { file_writes, permission_changes, node_registration, reward_claims } => try_set_pause(deps, env, file_writes, permission_changes, node_registration, reward_claims),
+        HandleMsg::AddNodeAllowlist { addresses } => try_update_node_allowlist(deps, env, addresses, true),
+        HandleMsg::RemoveNodeAllowlist { addresses } => try_update_node_allowlist(deps, env, addresses, false),
+        HandleMsg::FundRewardPool { } => try_fund_reward_pool(deps, env),
+        HandleMsg::SlashNode { address, percent } => try_slash_node(deps, env, address, percent),
+        HandleMsg::WithdrawSlashedStake { recipient } => try_withdraw_slashed_stake(deps, env, recipient),
+    }
+}
+
+// Admin commands always go through so the contract can be brought back from StopAll.
+// StopWrites still lets users make viewing keys so they can read what is already stored.
+fn check_status(status: ContractStatus, msg: &HandleMsg) -> StdResult<()> {
+    let allowed = match msg {
+        HandleMsg::ChangeAdmin { .. }
+        | HandleMsg::AcceptAdmin { .. }
+        | HandleMsg::UpdateConfig { .. }
+        | HandleMsg::SetContractStatus { .. }
+        | HandleMsg::SetPause { .. }
+        | HandleMsg::AddNodeAllowlist { .. }
+        | HandleMsg::RemoveNodeAllowlist { .. }
+        | HandleMsg::FundRewardPool { .. }
+        | HandleMsg::SlashNode { .. }
+        | HandleMsg::WithdrawSlashedStake { .. } => true,
+        HandleMsg::CreateViewingKey { .. } => status != ContractStatus::StopAll,
+        _ => status == ContractStatus::Normal,
+    };
+
+    if !allowed {
+        return Err(StdError::generic_err(format!("This action is disabled while the contract status is {:?}", status)));
+    }
+    Ok(())
+}
+
+// Which pause category a message falls under. Messages that return None can't be paused.
+fn operation_of(msg: &HandleMsg) -> Option<Operation> {
+    match msg {
+        HandleMsg::InitAddress { .. }
+        | HandleMsg::Create { .. }
+        | HandleMsg::CreateFolder { .. }
+        | HandleMsg::CreateMulti { .. }
+        | HandleMsg::Remove { .. }
+        | HandleMsg::RemoveMulti { .. }
+        | HandleMsg::Move { .. }
+        | HandleMsg::MoveMulti { .. }
+        | HandleMsg::ForgetMe { .. }
+        | HandleMsg::SweepForgotten { .. }
+        | HandleMsg::RegisterStorageRoot { .. }
+        | HandleMsg::CreateManifest { .. }
+        | HandleMsg::AppendChunks { .. }
+        | HandleMsg::FinalizeManifest { .. }
+        | HandleMsg::BeginUpload { .. }
+        | HandleMsg::UploadChunk { .. }
+        | HandleMsg::CommitUpload { .. }
+        | HandleMsg::AbortUpload { .. }
+        | HandleMsg::Batch { .. } => Some(Operation::FileWrites),
+        HandleMsg::AllowRead { .. }
+        | HandleMsg::DisallowRead { .. }
+        | HandleMsg::ResetRead { .. }
+        | HandleMsg::AllowWrite { .. }
+        | HandleMsg::DisallowWrite { .. }
+        | HandleMsg::ResetWrite { .. }
+        | HandleMsg::ChangeOwner { .. } => Some(Operation::PermissionChanges),
+        HandleMsg::InitNode { .. }
+        | HandleMsg::UpdateNode { .. }
+        | HandleMsg::UpdateNodeInfo { .. }
+        | HandleMsg::RemoveNode { .. }
+        | HandleMsg::NodeHeartbeat { .. }
+        | HandleMsg::ConfirmStorage { .. } => Some(Operation::NodeRegistration),
+        HandleMsg::ClaimReward { .. }
+        | HandleMsg::BindClaimCode { .. }
+        | HandleMsg::WithdrawRewards { .. }
+        | HandleMsg::SettleEscrow { .. }
+        | HandleMsg::WithdrawEscrow { .. }
+        | HandleMsg::DistributeEpoch { .. }
+        | HandleMsg::RequestChallenge { .. }
+        | HandleMsg::AnswerChallenge { .. } => Some(Operation::RewardClaims),
+        // WithdrawStake has no category: stake that is done unbonding can always be taken out
+        _ => None,
     }
 }
 
@@ -65,12 +209,28 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
 ) -> StdResult<Binary> {
+    let msg = msg.canonicalize_paths()?;
     match msg {
         QueryMsg::YouUpBro {address} => to_binary(&try_you_up_bro(deps, address)?),
         QueryMsg::GetNodeCoins {address} => to_binary(&pub_query_coins(deps, address)?),
         QueryMsg::GetNodeIP {index} => to_binary(&try_get_ip(deps, index)?),
-        QueryMsg::GetNodeList {size} => to_binary(&try_get_top_x(deps, size)?),
+        QueryMsg::GetNodeList {start_after, limit, min_score} => to_binary(&try_get_top_x(deps, start_after, limit, min_score)?),
+        QueryMsg::GetNode {ip} => to_binary(&try_get_node(deps, ip)?),
         QueryMsg::GetNodeListSize {} => to_binary(&try_get_node_list_size(deps)?),
+        QueryMsg::GetConfig {} => to_binary(&query_config(deps)?),
+        QueryMsg::GetPauseMatrix {} => to_binary(&query_pause_matrix(deps)?),
+        QueryMsg::GetScoreHistory { ip } => to_binary(&query_score_history(deps, ip)?),
+        QueryMsg::GetChallenge { ip } => to_binary(&query_challenge(deps, ip)?),
+        QueryMsg::GetRewardPool {} => to_binary(&query_reward_pool(deps)?),
+        QueryMsg::GetStake { address } => to_binary(&query_stake(deps, address)?),
+        QueryMsg::GetUnderReplicated { start, limit } => to_binary(&query_under_replicated(deps, start, limit)?),
+        QueryMsg::SelectNodes { count, file_hint } => to_binary(&query_select_nodes(deps, count, file_hint)?),
+        QueryMsg::FindNodes { start_after, size, region, min_free_bytes } => to_binary(&try_find_nodes(deps, start_after, size, region, min_free_bytes)?),
+        QueryMsg::GetEscrow { path } => to_binary(&query_escrow(deps, path)?),
+        QueryMsg::GetEscrowBalance { address } => to_binary(&query_escrow_balance(deps, address)?),
+        QueryMsg::GetEpochPayouts { address } => to_binary(&query_epoch_payouts(deps, address)?),
+        QueryMsg::GetNamespaceRoot { address } => to_binary(&query_namespace_root(deps, address)?),
+        QueryMsg::GetNamespaceSnapshot { address, height } => to_binary(&query_namespace_snapshot(deps, address, height)?),
         _ => authenticated_queries(deps, msg),
     }
 }
@@ -95,6 +255,11 @@ fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
             return match msg {
                 QueryMsg::GetContents { path, behalf, .. } => to_binary(&query_file(deps, path, &behalf)?),
                 QueryMsg::GetWalletInfo { behalf, .. } => to_binary(&query_wallet_info(deps, &behalf)?),
+                QueryMsg::GetManifest { path, behalf, start, limit, .. } => to_binary(&query_manifest(deps, path, &behalf, start, limit)?),
+                QueryMsg::VerifyContent { path, behalf, digest, .. } => to_binary(&query_verify_content(deps, path, &behalf, digest)?),
+                QueryMsg::GetNamespaceProof { path, behalf, .. } => to_binary(&query_namespace_proof(deps, path, &behalf)?),
+                QueryMsg::GetPlacement { path, behalf, .. } => to_binary(&query_placement(deps, path, &behalf)?),
+                QueryMsg::GetUploadSession { session, behalf, .. } => to_binary(&query_upload_session(deps, session, &behalf)?),
                 _ => panic!("How did this even get to this stage. It should have been processed.")
             };
         }
@@ -106,15 +271,41 @@ fn authenticated_queries<S: Storage, A: Api, Q: Querier>(
 
 fn try_init_node<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
+    env: Env,
     ip: String,
     address: String,
 ) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let ip = normalize_endpoint(&ip)?;
+
+    // The payout address has to be the one signing, otherwise anyone could register a node paying someone else
+    if address != signer.as_str() {
+        return Err(StdError::generic_err("Node address must be the address registering the node"));
+    }
+
+    if node_exists(&deps.storage, &ip) {
+        return Err(StdError::generic_err(format!("A node is already registered at {}", ip)));
+    }
+    if let Some(existing) = node_at_host(&deps.storage, &ip) {
+        return Err(StdError::generic_err(format!("A node is already registered at {}", existing)));
+    }
+
+    if load_node_owner(&deps.storage, &address).is_some() {
+        return Err(StdError::generic_err("This address already has a node registered"));
+    }
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    if state.config.node_allowlist_enabled && !is_node_allowed(&deps.storage, &address) {
+        return Err(StdError::generic_err("This address is not on the node allowlist"));
+    }
 
-    push_node(&mut deps.storage, ip, address);
+    let stake = bond_stake(&mut deps.storage, &address, &env.message.sent_funds)?;
+    let score = start_tracking(&mut deps.storage, &address, env.block.height)?;
+    push_node(&mut deps.storage, ip, address, score);
 
     Ok(HandleResponse {
         messages: vec![],
-        log: vec![],
+        log: vec![log("stake", stake)],
         data: Some(to_binary("OK")?),
     })
 }
@@ -124,45 +315,80 @@ fn try_get_ip<S: Storage, A: Api, Q: Querier>(
     index: u64,
 ) -> StdResult<HandleResponse> {
 
+    let ip = get_node(&deps.storage, index)
+        .ok_or_else(|| StdError::not_found(format!("Node at index {}", index)))?;
+    // Nodes registered before endpoints were validated may not parse
+    let endpoint = NodeEndpoint::parse(&ip)
+        .map_err(|e| StdError::generic_err(format!("Node at index {} has the endpoint '{}', which can't be parsed: {}", index, ip, e)))?;
+
     Ok(HandleResponse {
         messages: vec![],
         log: vec![],
-        data: Some(to_binary(&get_node(&deps.storage, index))?),
+        data: Some(to_binary(&endpoint)?),
     })
 }
 
+// Pages through the node list from the highest score down. Pass the ip of the last node of a page as
+// `start_after` to get the next one. Scores can change between calls, so nodes may shift across pages.
 fn try_get_top_x<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-    size: u64,
-) -> StdResult<HandleResponse> {
-
-    let size = cmp::min(size, get_node_size(&deps.storage));
-
-    let index_node = &get_node(&deps.storage, 0);
+    start_after: Option<String>,
+    limit: Option<u64>,
+    min_score: Option<u32>,
+) -> StdResult<NodeListResponse> {
+
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    let max = cmp::min(MAX_NODE_PAGE, config.config.max_node_list_size);
+    let limit = cmp::min(limit.unwrap_or(max), max);
+    let start = rank_after(deps, start_after)?;
+
+    Ok(NodeListResponse {
+        nodes: get_top_nodes(&deps.storage, start, limit, min_score.unwrap_or(0)),
+    })
+}
 
-    let mut nodes = vec!(index_node.clone());
+// Rank of the node following `start_after`, or the top of the list if None
+fn rank_after<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<String>,
+) -> StdResult<u64> {
+    match start_after {
+        Some(ip) => {
+            let ip = normalize_endpoint(&ip)?;
+            Ok(node_rank(&deps.storage, &ip).ok_or_else(|| StdError::not_found(format!("Node {}", ip)))? + 1)
+        }
+        None => Ok(0),
+    }
+}
 
-    if size <= 1  {
-        return Ok(HandleResponse {
-            messages: vec![],
-            log: vec![],
-            data: Some(to_binary(&nodes)?),
-        });
+fn try_get_node<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ip: String,
+) -> StdResult<NodeInfo> {
+    let ip = normalize_endpoint(&ip)?;
+    if !node_exists(&deps.storage, &ip) {
+        return Err(StdError::not_found(format!("Node {}", ip)));
     }
+    let node = load_node_data(&deps.storage, ip.clone());
+    Ok(node_info(ip, node))
+}
 
+// Like GetNodeList, but filtered. A filter that matches few nodes can stop the walk before `size` are
+// found, so `next` is handed back as the `start_after` of the next call until the list runs out
+fn try_find_nodes<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<String>,
+    size: u64,
+    region: Option<String>,
+    min_free_bytes: Option<u64>,
+) -> StdResult<FindNodesResponse> {
 
-    let mut x = 1;
-    while x < size {
-        let new_node = &get_node(&deps.storage, x);
-        nodes.push(new_node.clone());
-        x += 1;
-    } 
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    let size = cmp::min(size, cmp::min(MAX_NODE_PAGE, config.config.max_node_list_size));
+    let start = rank_after(deps, start_after)?;
 
-    Ok(HandleResponse {
-        messages: vec![],
-        log: vec![],
-        data: Some(to_binary(&nodes)?),
-    })
+    let (nodes, next) = find_nodes(&deps.storage, start, size, region, min_free_bytes.unwrap_or(0));
+    Ok(FindNodesResponse { nodes, next })
 }
 
 fn try_get_node_list_size<S: Storage, A: Api, Q: Querier>(
@@ -181,11 +407,24 @@ mod tests {
     // use std::vec;
     use super::*;
     use cosmwasm_std::testing::{mock_dependencies, mock_env};
-    use cosmwasm_std::{coins, from_binary, HumanAddr};
+    use cosmwasm_std::{coins, from_binary, BankMsg, Coin, CosmosMsg, HumanAddr, Uint128, WasmMsg};
     
-    use crate::msg::{FileResponse, HandleAnswer, WalletInfoResponse};
+    use crate::msg::{FileResponse, HandleAnswer, WalletInfoResponse, ConfigResponse, ConfigUpdate, ScoreHistoryResponse, RewardPoolResponse, PlacementInfo, UnderReplicatedResponse, EpochPayoutsResponse, ManifestResponse, VerifyContentResponse, NamespaceProofResponse};
     use crate::viewing_key::ViewingKey;
-    use crate::backend::{make_file, File};
+    use crate::backend::{make_folder, File};
+    use crate::nodes::{add_coins, set_node_score, NodeProfile};
+    use crate::state::RewardSource;
+    use crate::staking::Stake;
+    use crate::escrow::Escrow;
+    use crate::rewards::EpochPayout;
+    use crate::manifest::{ChunkDescriptor, NewChunk};
+    use crate::upload::UploadSession;
+    use crate::epoch::{record_file_proof, record_proof};
+    use crate::content::{body_references, content_digest, content_hash};
+    use crate::merkle::{hash_leaf, merkle_proof, merkle_root};
+    use crate::namespace_root::{namespace_leaf, verify_namespace_proof, NamespaceSnapshot, NamespaceTree};
+    use crate::path::{canonical_path, parent_path, MAX_PATH_DEPTH, MAX_SEGMENT_LENGTH};
+    use crate::batch::{FileOpResult, PermissionChange};
 
     fn init_for_test<S: Storage, A: Api, Q: Querier> (
         deps: &mut Extern<S, A, Q>,
@@ -250,8 +489,8 @@ mod tests {
         let size: u64 = from_binary(&result.data.unwrap()).unwrap();
         println!("{:#?}", &size);
 
-        let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("secret123456789") };
+        let env = mock_env("anyone", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("anyone") };
         let _res = handle(&mut deps, env, msg).unwrap();
 
         let query_res: Binary = query(&deps, QueryMsg::GetNodeListSize {  }).unwrap();
@@ -264,8 +503,9 @@ mod tests {
 
         let query_res: Binary = query(&deps, QueryMsg::GetNodeIP { index: (s) }).unwrap();
         let result:HandleResponse = from_binary(&query_res).unwrap();
-        let ip:String = from_binary(&result.data.unwrap()).unwrap();
+        let ip: NodeEndpoint = from_binary(&result.data.unwrap()).unwrap();
         println!("{:#?}", &ip);
+        assert_eq!(ip, NodeEndpoint::Ipv4 { address: String::from("192.168.0.1"), port: None });
 
     }
 
@@ -305,7 +545,7 @@ mod tests {
 
         // Create File - Nug: We don't actually use "anyone/test" throughout this test. Should we just delete this paragraph? - Bi
         let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/test/") , pkey: String::from("test"), skey: String::from("test")};
+        let msg = HandleMsg::CreateFolder { path: String::from("anyone/test/") };
         let _res = handle(&mut deps, env, msg).unwrap();
 
         // Create File
@@ -469,7 +709,7 @@ mod tests {
 
         // Create folder test/
         let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::Create { contents: String::from("<content inside test/ folder>"), path: String::from("anyone/test/") , pkey: String::from("test"), skey: String::from("test")};
+        let msg = HandleMsg::CreateFolder { path: String::from("anyone/test/") };
         let _res = handle(&mut deps, env, msg).unwrap();
 
         // Create File
@@ -518,7 +758,7 @@ mod tests {
 
         // Create File
         let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::Create { contents: String::from("content of meme/ folder "), path: String::from("anyone/meme/") , pkey: String::from("public key"), skey: String::from("secret key")};
+        let msg = HandleMsg::CreateFolder { path: String::from("anyone/meme/") };
         let _res = handle(&mut deps, env, msg).unwrap();
         
         // Create Multi File
@@ -561,14 +801,10 @@ mod tests {
         let vk = init_for_test(&mut deps, String::from("anyone"));
 
         // Create 3 folders (test/ meme_folder/ pepe/)
-        let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::CreateMulti { 
-                contents_list: vec!(String::from("<content inside test/>"), String::from("<content inside meme_folder/>"), String::from("<content inside pepe/>")),  
-                path_list: vec!(String::from("anyone/test/"), String::from("anyone/meme_folder/"), String::from("anyone/pepe/")), 
-                pkey_list: vec!(String::from("test"), String::from("test"), String::from("test")), 
-                skey_list: vec!(String::from("test"), String::from("test"), String::from("test"))
-        };
-        let _res = handle(&mut deps, env, msg).unwrap();
+        for path in ["anyone/test/", "anyone/meme_folder/", "anyone/pepe/"].iter() {
+            let env = mock_env("anyone", &[]);
+            let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: path.to_string() }).unwrap();
+        }
 
         // Create 2 Files phrog1.png and phrog2.png
         let env = mock_env("anyone", &[]);
@@ -625,14 +861,10 @@ mod tests {
         let vk2 = init_for_test(&mut deps, String::from("alice"));
 
         // Create 3 folders (test/ meme_folder/ pepe/)
-        let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::CreateMulti { 
-                contents_list: vec!(String::from("<content inside test/>"), String::from("<content inside meme_folder/>"), String::from("<content inside junior/>")),  
-                path_list: vec!(String::from("anyone/test/"), String::from("anyone/meme_folder/"), String::from("anyone/junior/")), 
-                pkey_list: vec!(String::from("test"), String::from("test"), String::from("test")), 
-                skey_list: vec!(String::from("test"), String::from("test"), String::from("test"))
-        };
-        let _res = handle(&mut deps, env, msg).unwrap();
+        for path in ["anyone/test/", "anyone/meme_folder/", "anyone/junior/"].iter() {
+            let env = mock_env("anyone", &[]);
+            let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: path.to_string() }).unwrap();
+        }
 
         // Create 2 Files bunny1.png and bunny2.png
         let env = mock_env("anyone", &[]);
@@ -658,7 +890,7 @@ mod tests {
 
         // lets make a folder inside of alice's root directory to store her new bunny in 
         let env = mock_env("alice", &[]);
-        let msg = HandleMsg::Create { contents: "bunnys go here".to_string(), path: String::from("alice/bunny_home/"), pkey: "test".to_string(), skey: "test".to_string() };
+        let msg = HandleMsg::CreateFolder { path: String::from("alice/bunny_home/") };
         let _res = handle(&mut deps, env, msg).unwrap();
         println!("Successfully created alice/bunny_home/:\n {:#?}", _res);
 
@@ -695,14 +927,10 @@ mod tests {
         let vk2 = init_for_test(&mut deps, String::from("alice"));
 
         // Create 2 folders (test/, junior/)
-        let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::CreateMulti { 
-                contents_list: vec!(String::from("<content inside test/>"), String::from("<content inside junior/>")),  
-                path_list: vec!(String::from("anyone/test/"), String::from("anyone/junior/")), 
-                pkey_list: vec!(String::from("test"), String::from("test")), 
-                skey_list: vec!(String::from("test"), String::from("test"))
-        };
-        let _res = handle(&mut deps, env, msg).unwrap();       
+        for path in ["anyone/test/", "anyone/junior/"].iter() {
+            let env = mock_env("anyone", &[]);
+            let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: path.to_string() }).unwrap();
+        }
 
         // Create bunny.png
         let env = mock_env("anyone", &[]);
@@ -756,7 +984,7 @@ mod tests {
 
         // Create Folder Test
         let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::Create { contents: String::from("<content of test/ folder>"), path: String::from("anyone/test/") , pkey: String::from("test"), skey: String::from("test")};
+        let msg = HandleMsg::CreateFolder { path: String::from("anyone/test/") };
         let _res = handle(&mut deps, env, msg).unwrap();
 
         // Create File
@@ -791,7 +1019,7 @@ mod tests {
         // Get File with Anyone's viewing key
         let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/test/"), behalf: HumanAddr("anyone".to_string()), key: vk.to_string() });
         let value: FileResponse = from_binary(&query_res.unwrap()).unwrap();
-        let test = make_file("anyone", "<content of test/ folder>");
+        let test = make_folder("anyone");
         assert_eq!(test, value.file);
     }
 
@@ -803,7 +1031,7 @@ mod tests {
 
         // Create File
         let env = mock_env("anyone", &[]);
-        let msg = HandleMsg::Create { contents: String::from("Rainbows"), path: String::from("anyone/test/") , pkey: String::from("public key"), skey: String::from("secret key")};
+        let msg = HandleMsg::CreateFolder { path: String::from("anyone/test/") };
         let _res = handle(&mut deps, env, msg).unwrap();   
         
         // Get File with viewing key to see the owner
@@ -846,4 +1074,2096 @@ mod tests {
         println!("alice gave ownership back to anyone --> {:#?}", value.file);
 
     }
+
+    #[test]
+    fn admin_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let query_res = query(&deps, QueryMsg::GetConfig {}).unwrap();
+        let value: ConfigResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.admin, HumanAddr("creator".to_string()));
+        assert_eq!(value.status, ContractStatus::Normal);
+
+        // Only the admin can nominate a new admin
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ChangeAdmin { address: HumanAddr("alice".to_string()) };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::ChangeAdmin { address: HumanAddr("alice".to_string()) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Nobody but alice can accept
+        let env = mock_env("anyone", &[]);
+        let res = handle(&mut deps, env, HandleMsg::AcceptAdmin {});
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::AcceptAdmin {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetConfig {}).unwrap();
+        let value: ConfigResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.admin, HumanAddr("alice".to_string()));
+        assert_eq!(value.pending_admin, None);
+
+        // The old admin has lost its powers
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetContractStatus { level: ContractStatus::StopWrites };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { max_contents_size: Some(4), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Contents over the new limit are rejected
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("too big"), path: String::from("anyone/pepe.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::SetContractStatus { level: ContractStatus::StopWrites };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("ok"), path: String::from("anyone/pepe.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // Viewing keys can still be made while writes are stopped
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CreateViewingKey { entropy: "supbro".to_string(), padding: None };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::SetContractStatus { level: ContractStatus::StopAll };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CreateViewingKey { entropy: "supbro".to_string(), padding: None };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::SetContractStatus { level: ContractStatus::Normal };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("ok"), path: String::from("anyone/pepe.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // With a write fee, new files have to come with at least that much of a storage payment
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { write_fee: Some(Uint128(100)), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetConfig {}).unwrap();
+        let value: ConfigResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.config.write_fee, Uint128(100));
+
+        let env = mock_env("anyone", &coins(99, "uscrt"));
+        let msg = HandleMsg::Create { contents: String::from("ok"), path: String::from("anyone/paid.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let err = handle(&mut deps, env, msg).unwrap_err();
+        assert_eq!(err.to_string(), "Generic error: Creating a file costs at least 100uscrt");
+
+        let env = mock_env("anyone", &coins(100, "uscrt"));
+        let msg = HandleMsg::Create { contents: String::from("ok"), path: String::from("anyone/paid.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // CreateMulti and Batch pay the fee for each file they create out of what is sent along
+        let env = mock_env("anyone", &coins(199, "uscrt"));
+        let msg = HandleMsg::CreateMulti {
+            contents_list: vec!(String::from("x"), String::from("y")),
+            path_list: vec!(String::from("anyone/x.txt"), String::from("anyone/y.txt")),
+            pkey_list: vec!(String::from("a"), String::from("a")),
+            skey_list: vec!(String::from("test"), String::from("test")),
+        };
+        let err = handle(&mut deps, env, msg.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "Generic error: Creating 2 files costs at least 200uscrt");
+        let env = mock_env("anyone", &coins(201, "uscrt"));
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/x.txt") }).unwrap();
+        let value: Escrow = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(101));
+        let query_res = query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/y.txt") }).unwrap();
+        let value: Escrow = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(100));
+
+        let ops = vec![
+            FileOp::Copy { from_path: String::from("anyone/paid.jpg"), to_path: String::from("anyone/copy.jpg") },
+            FileOp::Remove { path: String::from("anyone/x.txt") },
+        ];
+        let env = mock_env("anyone", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::Batch { ops: ops.clone() }).is_err());
+        let env = mock_env("anyone", &coins(100, "uscrt"));
+        let _res = handle(&mut deps, env, HandleMsg::Batch { ops }).unwrap();
+    }
+
+    #[test]
+    fn pause_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::SetPause { file_writes: None, permission_changes: None, node_registration: None, reward_claims: Some(true) };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetPause { file_writes: None, permission_changes: None, node_registration: None, reward_claims: Some(true) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetPauseMatrix {}).unwrap();
+        let value: PauseMatrix = from_binary(&query_res).unwrap();
+        assert_eq!(value, PauseMatrix { file_writes: false, permission_changes: false, node_registration: false, reward_claims: true });
+
+        // Claims are frozen
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("test"), key: String::from("test"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+        println!("{:#?}", res);
+
+        // Uploads still work
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/pepe.jpg") , pkey: String::from("test"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetPause { file_writes: Some(true), permission_changes: None, node_registration: None, reward_claims: Some(false) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Remove { path: String::from("anyone/pepe.jpg") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // Permission changes are their own category
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AllowRead { path: String::from("anyone/pepe.jpg"), address_list: vec!(String::from("alice")) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/pepe.jpg"), behalf: HumanAddr("anyone".to_string()), key: vk.to_string() });
+        assert!(query_res.is_ok());
+    }
+
+    #[test]
+    fn node_registration_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        // Can't register a node paying out to someone else
+        let env = mock_env("anyone", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("alice") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("anyone", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("anyone") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Same ip twice
+        let env = mock_env("alice", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("alice") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // Same address twice
+        let env = mock_env("anyone", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.2"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { node_allowlist_enabled: Some(true), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("alice", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.2"), address: String::from("alice") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::AddNodeAllowlist { addresses: vec!(HumanAddr("alice".to_string())) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("alice", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.2"), address: String::from("alice") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeListSize {  }).unwrap();
+        let result:HandleResponse = from_binary(&query_res).unwrap();
+        let size: u64 = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(size, 2);
+    }
+
+    #[test]
+    fn node_remove_and_update_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in vec!["anyone", "alice", "bob"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // Someone without a node can't remove one
+        let env = mock_env("charlie", &[]);
+        let res = handle(&mut deps, env, HandleMsg::RemoveNode {});
+        assert!(res.is_err());
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        // The last-ranked node takes anyone's rank. With equal scores it has no reason to sink further
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let ips: Vec<String> = value.nodes.iter().map(|n| n.ip.clone()).collect();
+        assert_eq!(ips, vec!(String::from("192.168.0.2"), String::from("192.168.0.1")));
+
+        // Can't move onto an ip that is taken
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::UpdateNode { new_ip: String::from("192.168.0.2") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::UpdateNode { new_ip: String::from("10.0.0.1") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let ips: Vec<String> = value.nodes.iter().map(|n| n.ip.clone()).collect();
+        assert_eq!(ips, vec!(String::from("192.168.0.2"), String::from("10.0.0.1")));
+        assert_eq!(value.nodes[1].address, String::from("alice"));
+
+        // The old ip is free again and anyone can register a fresh node
+        let env = mock_env("anyone", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("anyone") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeListSize {  }).unwrap();
+        let result:HandleResponse = from_binary(&query_res).unwrap();
+        let size: u64 = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(size, 3);
+    }
+
+    #[test]
+    fn node_ranking_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in vec!["anyone", "alice", "bob", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        set_node_score(&mut deps.storage, String::from("192.168.0.2"), 900);
+        set_node_score(&mut deps.storage, String::from("192.168.0.0"), 100);
+        set_node_score(&mut deps.storage, String::from("192.168.0.3"), 700);
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let scores: Vec<u32> = value.nodes.iter().map(|n| n.score).collect();
+        assert_eq!(scores, vec!(900, 700, 500, 100));
+        assert_eq!(value.nodes[0].address, String::from("bob"));
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(2), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 2);
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: Some(500) }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let ips: Vec<String> = value.nodes.iter().map(|n| n.ip.clone()).collect();
+        assert_eq!(ips, vec!(String::from("192.168.0.2"), String::from("192.168.0.3"), String::from("192.168.0.1")));
+
+        // Removing the top node keeps the rest in order
+        let env = mock_env("bob", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let scores: Vec<u32> = value.nodes.iter().map(|n| n.score).collect();
+        assert_eq!(scores, vec!(700, 500, 100));
+    }
+
+    #[test]
+    fn reputation_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+        let _vk2 = init_for_test(&mut deps, String::from("alice"));
+
+        for name in ["pepe.jpg", "pepe2.jpg"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: format!("anyone/{}", name), pkey: String::from("test"), skey: String::from("test")};
+            let _res = handle(&mut deps, env, msg).unwrap();
+            register_test_root(&mut deps, &format!("anyone/{}", name));
+        }
+
+        for (i, address) in vec!["bob", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        for name in ["pepe.jpg", "pepe2.jpg"].iter() {
+            let file = placement(&deps, &vk, &format!("anyone/{}", name)).file;
+            let _res = confirm_storage(&mut deps, "charlie", &file);
+        }
+
+        // Too soon after registering
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::NodeHeartbeat {});
+        assert!(res.is_err());
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 100;
+        let _res = handle(&mut deps, env, HandleMsg::NodeHeartbeat {}).unwrap();
+
+        // alice can't read anyone/pepe.jpg so her report is refused
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.1"), path: String::from("anyone/pepe.jpg") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // bob never held the file, so he can't have failed to serve it
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.0"), path: String::from("anyone/pepe.jpg") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This node isn't holding this file");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.1"), path: String::from("anyone/pepe.jpg") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // The same file can't be held against the node again straight away, whoever reports it
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.1"), path: String::from("anyone/pepe.jpg") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This node was already reported for this file recently");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AllowRead { path: String::from("anyone/pepe.jpg"), address_list: vec!(String::from("alice")) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.1"), path: String::from("anyone/pepe.jpg") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This node was already reported for this file recently");
+
+        // Another file the node holds is a separate failure
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::ReportNodeFailure { ip: String::from("192.168.0.1"), path: String::from("anyone/pepe2.jpg") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let scores: Vec<u32> = value.nodes.iter().map(|n| n.score).collect();
+        assert_eq!(scores, vec!(505, 450));
+
+        // After a long silence anyone can apply the decay, without the nodes checking in
+        let mut env = mock_env("alice", &[]);
+        env.block.height += 3100;
+        let res = handle(&mut deps, env, HandleMsg::DecayScores { start: 0, limit: 10 }).unwrap();
+        assert_eq!(res.log[0].value, "2");
+        assert_eq!(res.log[1].value, "none");
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        let scores: Vec<u32> = value.nodes.iter().map(|n| n.score).collect();
+        assert_eq!(scores, vec!(505 - 30, 450 - 30));
+
+        let query_res = query(&deps, QueryMsg::GetScoreHistory { ip: String::from("192.168.0.0") }).unwrap();
+        let value: ScoreHistoryResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.score, 505 - 30);
+        assert_eq!(value.history.len(), 2);
+        assert_eq!(value.history[1].reason, String::from("decay"));
+
+        // Decay already applied isn't applied again, and the next heartbeat only adds its bonus
+        let mut env = mock_env("alice", &[]);
+        env.block.height += 3100;
+        let res = handle(&mut deps, env, HandleMsg::DecayScores { start: 0, limit: 10 }).unwrap();
+        assert_eq!(res.log[0].value, "0");
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 3100;
+        let _res = handle(&mut deps, env, HandleMsg::NodeHeartbeat {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetScoreHistory { ip: String::from("192.168.0.0") }).unwrap();
+        let value: ScoreHistoryResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.score, 505 - 30 + 5);
+        assert_eq!(value.history[2].reason, String::from("heartbeat"));
+    }
+
+    fn request_challenge<S: Storage, A: Api, Q: Querier>(
+        deps: &mut Extern<S, A, Q>,
+        node: &str,
+        height: u64,
+    ) -> crate::challenge::Challenge {
+        let mut env = mock_env(node, &[]);
+        env.block.height = height;
+        let res = handle(deps, env, HandleMsg::RequestChallenge {}).unwrap();
+        match from_binary(&res.data.unwrap()).unwrap() {
+            HandleAnswer::Challenge { challenge } => challenge,
+            _ => panic!("Unexpected result from handle"),
+        }
+    }
+
+    fn test_chunks() -> Vec<Vec<u8>> {
+        vec!(b"chunk zero".to_vec(), b"chunk one".to_vec(), b"chunk two".to_vec())
+    }
+
+    fn register_test_root<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>, path: &str) {
+        let leaves: Vec<[u8; 32]> = test_chunks().iter().map(|c| hash_leaf(c)).collect();
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::RegisterStorageRoot { path: path.to_string(), merkle_root: Binary(merkle_root(&leaves).to_vec()), chunk_count: 3 };
+        let _res = handle(deps, env, msg).unwrap();
+    }
+
+    // Answers `challenge` on test_chunks() honestly
+    fn answer_challenge<S: Storage, A: Api, Q: Querier>(
+        deps: &mut Extern<S, A, Q>,
+        node: &str,
+        height: u64,
+        challenge: &crate::challenge::Challenge,
+    ) -> HandleResponse {
+        let chunks = test_chunks();
+        let leaves: Vec<[u8; 32]> = chunks.iter().map(|c| hash_leaf(c)).collect();
+        let index = challenge.chunk_index as usize;
+        let mut response = chunks[index].clone();
+        response.extend_from_slice(challenge.nonce.as_slice());
+        let proof = merkle_proof(&leaves, index).iter().map(|p| Binary(p.to_vec())).collect();
+
+        let mut env = mock_env(node, &[]);
+        env.block.height = height;
+        let msg = HandleMsg::AnswerChallenge { chunk: Binary(chunks[index].clone()), proof, response: Binary(sha_256(&response).to_vec()) };
+        handle(deps, env, msg).unwrap()
+    }
+
+    fn placement<S: Storage, A: Api, Q: Querier>(deps: &Extern<S, A, Q>, vk: &ViewingKey, path: &str) -> PlacementInfo {
+        let query_res = query(deps, QueryMsg::GetPlacement { behalf: HumanAddr::from("anyone"), path: path.to_string(), key: vk.to_string() }).unwrap();
+        from_binary(&query_res).unwrap()
+    }
+
+    // Has `node` pass the confirmation challenge for a file whose root is on test_chunks()
+    fn confirm_storage<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>, node: &str, file: &str) -> HandleResponse {
+        let env = mock_env(node, &[]);
+        let res = handle(deps, env, HandleMsg::ConfirmStorage { file: file.to_string() }).unwrap();
+        let challenge = match from_binary(&res.data.unwrap()).unwrap() {
+            HandleAnswer::Challenge { challenge } => challenge,
+            _ => panic!("Unexpected result from handle"),
+        };
+        answer_challenge(deps, node, 12345, &challenge)
+    }
+
+    #[test]
+    fn storage_challenge_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for name in ["data.bin", "copy.bin"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("stored off chain"), path: format!("anyone/{}", name), pkey: String::from("test"), skey: String::from("test")};
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        let leaves: Vec<[u8; 32]> = test_chunks().iter().map(|c| hash_leaf(c)).collect();
+        let root = merkle_root(&leaves);
+        register_test_root(&mut deps, "anyone/data.bin");
+
+        // A root that is already registered keeps its chunk count
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::RegisterStorageRoot { path: String::from("anyone/copy.bin"), merkle_root: Binary(root.to_vec()), chunk_count: 4 };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Nodes are only challenged on files they hold
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::RequestChallenge {});
+        assert!(res.is_err());
+
+        let file = placement(&deps, &vk, "anyone/data.bin").file;
+        let res = confirm_storage(&mut deps, "bob", &file);
+        assert_eq!(res.log[0].value, "confirmed");
+
+        // Honest answer
+        let height = 12345;
+        let challenge = request_challenge(&mut deps, "bob", height);
+        let res = answer_challenge(&mut deps, "bob", height, &challenge);
+        assert_eq!(res.log[0].value, "passed");
+
+        // Passing is paid through the epoch emission, not with credits on the spot
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeCoins { address: String::from("bob") }).unwrap();
+        let result:HandleResponse = from_binary(&query_res).unwrap();
+        let coins: u32 = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(coins, 0);
+
+        // One challenge per challenge_window
+        let mut env = mock_env("bob", &[]);
+        env.block.height = height + 49;
+        let res = handle(&mut deps, env, HandleMsg::RequestChallenge {});
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: A node can request one challenge every 50 blocks");
+
+        // A chunk that isn't in the tree fails and costs score instead of erroring
+        let challenge = request_challenge(&mut deps, "bob", height + 50);
+        let mut response = b"made up".to_vec();
+        response.extend_from_slice(challenge.nonce.as_slice());
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height = height + 50;
+        let msg = HandleMsg::AnswerChallenge { chunk: Binary(b"made up".to_vec()), proof: vec!(), response: Binary(sha_256(&response).to_vec()) };
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(res.log[0].value, "failed");
+
+        // Letting a challenge run out
+        let _challenge = request_challenge(&mut deps, "bob", height + 100);
+
+        let mut env = mock_env("alice", &[]);
+        env.block.height = height + 150;
+        let res = handle(&mut deps, env, HandleMsg::ExpireChallenge { ip: String::from("192.168.0.1") });
+        assert!(res.is_err());
+
+        let mut env = mock_env("alice", &[]);
+        env.block.height = height + 151;
+        let _res = handle(&mut deps, env, HandleMsg::ExpireChallenge { ip: String::from("192.168.0.1") }).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetScoreHistory { ip: String::from("192.168.0.1") }).unwrap();
+        let value: ScoreHistoryResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.score, 500 + 10 - 50 - 50);
+
+        // Both misses cost 10% of the stake
+        let query_res = query(&deps, QueryMsg::GetStake { address: HumanAddr("bob".to_string()) }).unwrap();
+        let stake: Stake = from_binary(&query_res).unwrap();
+        assert_eq!(stake.bonded, Uint128(810_000));
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeCoins { address: String::from("bob") }).unwrap();
+        let result:HandleResponse = from_binary(&query_res).unwrap();
+        let coins: u32 = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(coins, 0);
+
+        // Removing one of two files moves the other into its place in the node's list
+        register_test_root(&mut deps, "anyone/copy.bin");
+        let file = placement(&deps, &vk, "anyone/copy.bin").file;
+        let res = confirm_storage(&mut deps, "bob", &file);
+        assert_eq!(res.log[0].value, "confirmed");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Remove { path: String::from("anyone/data.bin") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let challenge = request_challenge(&mut deps, "bob", height + 200);
+        let res = answer_challenge(&mut deps, "bob", height + 200, &challenge);
+        assert_eq!(res.log[0].value, "passed");
+
+        // Removing the last file leaves the node nothing to be challenged on
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Remove { path: String::from("anyone/copy.bin") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height = height + 250;
+        let res = handle(&mut deps, env, HandleMsg::RequestChallenge {});
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This node holds no files with a registered storage root");
+    }
+
+    #[test]
+    fn claim_reward_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/pepe.jpg") , pkey: String::from("pepe"), skey: String::from("secret code")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        for (i, address) in vec!["bob", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i + 1), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // Only nodes can claim
+        let env = mock_env("alice", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // Unknown codes are an error, not a panic
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("nope"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        // A code has to be bound to a node before it can be redeemed, and only the uploader can bind it
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This claim code isn't bound to this node");
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::BindClaimCode { path: String::from("pepe"), node: HumanAddr::from("bob") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BindClaimCode { path: String::from("pepe"), node: HumanAddr::from("alice") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BindClaimCode { path: String::from("pepe"), node: HumanAddr::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Another node that learns the code can't redeem it
+        let env = mock_env("charlie", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This claim code isn't bound to this node");
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("wrong code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(res.log[1].value, "bob");
+
+        // A redeemed code can't be bound to another node and redeemed again
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BindClaimCode { path: String::from("pepe"), node: HumanAddr::from("charlie") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        // Replaying the same claim doesn't pay twice
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from("pepe"), key: String::from("secret code"), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeCoins { address: String::from("bob") }).unwrap();
+        let result:HandleResponse = from_binary(&query_res).unwrap();
+        let coins: u32 = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(coins, 1);
+
+        // Moving the file doesn't leave behind a blank code
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/pepe.jpg"), new_path: String::from("anyone/pepe2.jpg") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::ClaimReward { path: String::from(""), key: String::from(""), address: String::from("anyone") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn withdraw_rewards_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { reward_per_credit: Some(Uint128(100)), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Earn two credits for bob
+        let env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        for name in ["pepe", "frog"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: format!("anyone/{}.jpg", name), pkey: name.to_string(), skey: String::from("secret code")};
+            let _res = handle(&mut deps, env, msg).unwrap();
+
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::BindClaimCode { path: name.to_string(), node: HumanAddr::from("bob") };
+            let _res = handle(&mut deps, env, msg).unwrap();
+
+            let env = mock_env("bob", &[]);
+            let msg = HandleMsg::ClaimReward { path: name.to_string(), key: String::from("secret code"), address: String::from("anyone") };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // Only the admin funds the pool, and only in the reward denom
+        let env = mock_env("anyone", &coins(150, "uscrt"));
+        let res = handle(&mut deps, env, HandleMsg::FundRewardPool {});
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &coins(150, "token"));
+        let res = handle(&mut deps, env, HandleMsg::FundRewardPool {});
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &coins(150, "uscrt"));
+        let _res = handle(&mut deps, env, HandleMsg::FundRewardPool {}).unwrap();
+
+        // Can't withdraw more than the pool holds, and nothing is burned when that happens
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawRewards { amount: 2 });
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawRewards { amount: 1 }).unwrap();
+        assert_eq!(res.messages, vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr("cosmos2contract".to_string()),
+            to_address: HumanAddr("bob".to_string()),
+            amount: vec![Coin { denom: String::from("uscrt"), amount: Uint128(100) }],
+        })]);
+
+        let query_res = query(&deps, QueryMsg::GetRewardPool {}).unwrap();
+        let value: RewardPoolResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(50));
+
+        // Switch to minting a SNIP-20 token
+        let env = mock_env("creator", &[]);
+        let source = RewardSource::Snip20 { contract_addr: HumanAddr("token".to_string()), code_hash: String::from("hash") };
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { reward_source: Some(source), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawRewards { amount: 1 }).unwrap();
+        match &res.messages[0] {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => assert_eq!(contract_addr, &HumanAddr("token".to_string())),
+            _ => panic!("Expected a SNIP-20 mint"),
+        }
+
+        // All credits are spent
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawRewards { amount: 1 });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn staking_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        // Not enough stake, or the wrong coin
+        let env = mock_env("bob", &coins(999_999, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &coins(1_000_000, "token"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &coins(2_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Only the admin can slash, and the cut lands in the reward pool
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::SlashNode { address: HumanAddr("bob".to_string()), percent: 25 };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SlashNode { address: HumanAddr("bob".to_string()), percent: 25 };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetRewardPool {}).unwrap();
+        let value: RewardPoolResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(500_000));
+
+        // Removing the node starts the unbonding delay
+        let env = mock_env("bob", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetStake { address: HumanAddr("bob".to_string()) }).unwrap();
+        let stake: Stake = from_binary(&query_res).unwrap();
+        assert_eq!(stake, Stake { bonded: Uint128(0), unbonding: Uint128(1_500_000), release_height: 12345 + 10_000, denom: String::from("uscrt") });
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 9_999;
+        let res = handle(&mut deps, env, HandleMsg::WithdrawStake {});
+        assert!(res.is_err());
+
+        // Unbonding stake can still be slashed
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SlashNode { address: HumanAddr("bob".to_string()), percent: 10 };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Pausing node registration doesn't hold the stake back
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetPause { file_writes: None, permission_changes: None, node_registration: Some(true), reward_claims: None };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 10_000;
+        let res = handle(&mut deps, env, HandleMsg::WithdrawStake {}).unwrap();
+        assert_eq!(res.messages, vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr("cosmos2contract".to_string()),
+            to_address: HumanAddr("bob".to_string()),
+            amount: vec![Coin { denom: String::from("uscrt"), amount: Uint128(1_350_000) }],
+        })]);
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetPause { file_writes: None, permission_changes: None, node_registration: Some(false), reward_claims: None };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 10_000;
+        let res = handle(&mut deps, env, HandleMsg::WithdrawStake {});
+        assert!(res.is_err());
+
+        // With SNIP-20 rewards slashed stake is held for the admin instead of going to the pool
+        let env = mock_env("creator", &[]);
+        let source = RewardSource::Snip20 { contract_addr: HumanAddr("token".to_string()), code_hash: String::from("hash") };
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { reward_source: Some(source), stake_denom: Some(String::from("ustake")), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("bob", &coins(1_000_000, "ustake"));
+        env.block.height += 10_000;
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SlashNode { address: HumanAddr("bob".to_string()), percent: 10 };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetRewardPool {}).unwrap();
+        let value: RewardPoolResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(650_000));
+        assert_eq!(value.slashed_stake, vec![Coin { denom: String::from("ustake"), amount: Uint128(100_000) }]);
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawSlashedStake { recipient: HumanAddr::from("bob") });
+        assert!(res.is_err());
+
+        let env = mock_env("creator", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawSlashedStake { recipient: HumanAddr::from("creator") }).unwrap();
+        assert_eq!(res.messages, vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr("cosmos2contract".to_string()),
+            to_address: HumanAddr("creator".to_string()),
+            amount: vec![Coin { denom: String::from("ustake"), amount: Uint128(100_000) }],
+        })]);
+
+        let env = mock_env("creator", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawSlashedStake { recipient: HumanAddr::from("creator") });
+        assert!(res.is_err());
+
+        // bob leaves after his score has decayed. Stake is returned in the denom it was sent in, even after
+        // stake_denom changes, and can't be mixed with stake in the new denom.
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 13_000;
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { stake_denom: Some(String::from("uscrt")), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        env.block.height += 13_000;
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let res = handle(&mut deps, env, msg);
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This address still has stake in ustake. Withdraw it before staking in uscrt");
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height += 23_000;
+        let res = handle(&mut deps, env, HandleMsg::WithdrawStake {}).unwrap();
+        assert_eq!(res.messages, vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr("cosmos2contract".to_string()),
+            to_address: HumanAddr("bob".to_string()),
+            amount: vec![Coin { denom: String::from("ustake"), amount: Uint128(900_000) }],
+        })]);
+
+        // Coming back doesn't reset the score to the starting one
+        let mut env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        env.block.height += 23_000;
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes[0].score, 500 - 30);
+    }
+
+    #[test]
+    fn placement_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { replication_factor: Some(2), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        for name in ["a", "b"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: format!("anyone/{}.txt", name), pkey: name.to_string(), skey: String::from("test")};
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        for (i, address) in vec!["bob", "alice", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        let file_a = placement(&deps, &vk, "anyone/a.txt").file;
+        let file_b = placement(&deps, &vk, "anyone/b.txt").file;
+
+        // The root folder isn't tracked, both files start with no replicas. Files are listed by id, not path.
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 10 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.files.len(), 2);
+        assert_eq!(value.next, None);
+        assert!(value.files.iter().all(|file| !file.file.contains("anyone")));
+
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 1 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.files.len(), 1);
+        assert_eq!(value.next, Some(1));
+
+        // Only readers of the file can look up its placement
+        let res = query(&deps, QueryMsg::GetPlacement { behalf: HumanAddr::from("anyone"), path: String::from("anyone/a.txt"), key: String::from("wrong") });
+        assert!(res.is_err());
+
+        // A file needs a storage root before nodes can confirm it
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: file_a.clone() });
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This file has no storage root to confirm against");
+        register_test_root(&mut deps, "anyone/a.txt");
+        register_test_root(&mut deps, "anyone/b.txt");
+
+        // Only nodes confirm, only known files, and only once per file
+        let env = mock_env("anyone", &[]);
+        let res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: file_a.clone() });
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: String::from("anyone0/anyone/a.txt") });
+        assert!(res.is_err());
+
+        // A wrong answer to the confirmation challenge just doesn't count
+        let env = mock_env("bob", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: file_a.clone() }).unwrap();
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::AnswerChallenge { chunk: Binary(b"made up".to_vec()), proof: vec![], response: Binary(vec![0; 32]) };
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(res.log[0].value, "failed");
+        assert!(placement(&deps, &vk, "anyone/a.txt").nodes.is_empty());
+
+        let query_res = query(&deps, QueryMsg::GetStake { address: HumanAddr::from("bob") }).unwrap();
+        let stake: Stake = from_binary(&query_res).unwrap();
+        assert_eq!(stake.bonded, Uint128(1_000_000));
+
+        for address in vec!["bob", "alice"].iter() {
+            let res = confirm_storage(&mut deps, address, &file_a);
+            assert_eq!(res.log[0].value, "confirmed");
+        }
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: file_a.clone() });
+        assert!(res.is_err());
+
+        // Confirmations stop at replication_factor
+        let env = mock_env("charlie", &[]);
+        let res = handle(&mut deps, env, HandleMsg::ConfirmStorage { file: file_a.clone() });
+        assert_eq!(res.unwrap_err().to_string(), "Generic error: This file already has 2 replicas");
+
+        let _res = confirm_storage(&mut deps, "bob", &file_b);
+
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 10 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.files, vec![placement(&deps, &vk, "anyone/b.txt")]);
+        assert_eq!(value.files[0].nodes, vec![String::from("bob")]);
+        assert!(value.files[0].root.is_some());
+
+        // Replicas follow a moved file
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/b.txt"), new_path: String::from("anyone/c.txt") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        assert_eq!(placement(&deps, &vk, "anyone/c.txt").nodes, vec![String::from("bob")]);
+        let res = query(&deps, QueryMsg::GetPlacement { behalf: HumanAddr::from("anyone"), path: String::from("anyone/b.txt"), key: vk.to_string() });
+        assert!(res.is_err());
+
+        // A node leaving takes its replicas with it
+        let env = mock_env("alice", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 10 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.files.len(), 2);
+
+        // Overwriting a file means it has to be confirmed again
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("I'm happy"), path: String::from("anyone/c.txt"), pkey: String::from("c"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+        assert!(placement(&deps, &vk, "anyone/c.txt").nodes.is_empty());
+
+        // Sweeping after ForgetMe stops tracking everything in the old namespace, a page at a time. The
+        // namespace's folder comes first.
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::ForgetMe {}).unwrap();
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::SweepForgotten { address: HumanAddr::from("anyone"), limit: 1 }).unwrap();
+        assert_eq!(res.log, vec![log("swept", 1), log("remaining", 1)]);
+
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 10 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.files.len(), 2);
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::SweepForgotten { address: HumanAddr::from("anyone"), limit: 10 }).unwrap();
+        assert_eq!(res.log, vec![log("swept", 2), log("remaining", 0)]);
+
+        let env = mock_env("bob", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::SweepForgotten { address: HumanAddr::from("anyone"), limit: 10 }).is_err());
+
+        let query_res = query(&deps, QueryMsg::GetUnderReplicated { start: 0, limit: 10 }).unwrap();
+        let value: UnderReplicatedResponse = from_binary(&query_res).unwrap();
+        assert!(value.files.is_empty());
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::RequestChallenge {});
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn select_nodes_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in vec!["anyone", "alice", "bob", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        // charlie falls below the threshold
+        set_node_score(&mut deps.storage, String::from("192.168.0.3"), 50);
+
+        let query_res = query(&deps, QueryMsg::SelectNodes { count: 2, file_hint: String::from("pepe.jpg") }).unwrap();
+        let first: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(first.nodes.len(), 2);
+        assert_ne!(first.nodes[0].ip, first.nodes[1].ip);
+
+        // Same hint, same nodes
+        let query_res = query(&deps, QueryMsg::SelectNodes { count: 2, file_hint: String::from("pepe.jpg") }).unwrap();
+        let second: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(first, second);
+
+        // Asking for more than there are only returns the eligible nodes
+        let query_res = query(&deps, QueryMsg::SelectNodes { count: 10, file_hint: String::from("frog.jpg") }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 3);
+        assert!(value.nodes.iter().all(|node| node.address != "charlie"));
+    }
+
+    #[test]
+    fn node_profile_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in vec!["alice", "bob", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        let profile = |free: u64, region: &str| NodeProfile {
+            total_bytes: 1000,
+            free_bytes: free,
+            region: region.to_string(),
+            protocols: vec![String::from("https")],
+            price_per_gb_month: Uint128(5),
+        };
+
+        // Only nodes have a profile, and it has to make sense
+        let env = mock_env("anyone", &[]);
+        let res = handle(&mut deps, env, HandleMsg::UpdateNodeInfo { profile: profile(10, "eu") });
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let res = handle(&mut deps, env, HandleMsg::UpdateNodeInfo { profile: profile(2000, "eu") });
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNodeInfo { profile: profile(900, " EU ") }).unwrap();
+        let env = mock_env("bob", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNodeInfo { profile: profile(100, "eu") }).unwrap();
+        let env = mock_env("charlie", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNodeInfo { profile: profile(900, "us") }).unwrap();
+
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: None, size: 10, region: Some(String::from("eu")), min_free_bytes: None }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 2);
+        assert_eq!(value.nodes[0].profile, profile(900, "eu"));
+        assert_eq!(value.next, None);
+
+        // A full page hands back where it stopped, and the next call carries on from there
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: None, size: 1, region: Some(String::from("eu")), min_free_bytes: None }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 1);
+        assert_eq!(value.next, Some(value.nodes[0].ip.clone()));
+
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: value.next, size: 1, region: Some(String::from("eu")), min_free_bytes: None }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 1);
+        assert_eq!(value.nodes[0].address, "bob");
+
+        let res = query(&deps, QueryMsg::FindNodes { start_after: Some(String::from("10.9.9.9")), size: 1, region: None, min_free_bytes: None });
+        assert!(res.is_err());
+
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: None, size: 10, region: Some(String::from("eu")), min_free_bytes: Some(500) }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 1);
+        assert_eq!(value.nodes[0].address, "alice");
+
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: None, size: 10, region: None, min_free_bytes: Some(500) }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 2);
+
+        // The profile stays with the node when it moves
+        let env = mock_env("charlie", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNode { new_ip: String::from("10.0.0.1") }).unwrap();
+
+        let query_res = query(&deps, QueryMsg::FindNodes { start_after: None, size: 10, region: Some(String::from("us")), min_free_bytes: None }).unwrap();
+        let value: FindNodesResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes[0].ip, "10.0.0.1");
+    }
+
+    #[test]
+    fn node_endpoint_test() {
+        let ok = |input: &str| NodeEndpoint::parse(input).unwrap().to_string();
+        assert_eq!(ok(" 192.168.0.1:4000 "), "192.168.0.1:4000");
+        assert_eq!(ok("[2001:DB8:0:0::1]:4000"), "[2001:db8::1]:4000");
+        assert_eq!(ok("2001:db8::1"), "[2001:db8::1]");
+        assert_eq!(ok("Node1.Example.com.:443"), "node1.example.com:443");
+        assert_eq!(ok("/IP4/10.0.0.1/TCP/4000/p2p/QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N"), "/ip4/10.0.0.1/tcp/4000/p2p/QmYyQSo1c1Ym7orWxLYvCrM2EmxFTANf8wXmmE7DWjhx5N");
+        assert_eq!(NodeEndpoint::parse("1.2.3.4:80").unwrap(), NodeEndpoint::Ipv4 { address: String::from("1.2.3.4"), port: Some(80) });
+
+        for bad in ["", "garbage", "192.168.0.300", "1.2.3.4:0", "1.2.3.4:080", "1.2.3.4:70000", "[::1", "-bad.example.com", "a b.com", "/tcp/4000", "/ip4/1.2.3.4", "/ip4/1.2.3.4/smtp/25"].iter() {
+            assert!(NodeEndpoint::parse(bad).is_err(), "{} should be rejected", bad);
+        }
+
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("alice", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("not an endpoint"), address: String::from("alice") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("alice", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from(" 192.168.0.1:4000"), address: String::from("alice") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // The same endpoint written differently is still a duplicate
+        let env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1:4000 "), address: String::from("bob") };
+        let res = handle(&mut deps, env, msg);
+        assert!(res.is_err());
+
+        let env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("Storage.Example.com:4000"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(10), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes[1].ip, "storage.example.com:4000");
+        assert_eq!(value.nodes[1].endpoint, Some(NodeEndpoint::Hostname { host: String::from("storage.example.com"), port: Some(4000) }));
+
+        // Lookups take any spelling too
+        let res = query(&deps, QueryMsg::GetScoreHistory { ip: String::from("STORAGE.example.com:4000") });
+        assert!(res.is_ok());
+
+        // Another port or a multiaddr on a host that already has a node is a duplicate as well
+        for ip in ["192.168.0.1:5000", "192.168.0.1", "/ip4/192.168.0.1/tcp/4001", "/dns4/storage.example.com/tcp/4000"].iter() {
+            let env = mock_env("charlie", &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: ip.to_string(), address: String::from("charlie") };
+            let res = handle(&mut deps, env, msg);
+            assert!(res.unwrap_err().to_string().starts_with("Generic error: A node is already registered at"), "{} should be rejected", ip);
+        }
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::UpdateNode { new_ip: String::from("/ip4/192.168.0.1/tcp/4000") });
+        assert!(res.is_err());
+
+        // A node can move to another port on its own host, and the host it left is free again
+        let env = mock_env("alice", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNode { new_ip: String::from("/ip4/192.168.0.1/tcp/5000") }).unwrap();
+
+        let env = mock_env("alice", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::UpdateNode { new_ip: String::from("10.0.0.1") }).unwrap();
+
+        let env = mock_env("charlie", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1:5000"), address: String::from("charlie") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res: Binary = query(&deps, QueryMsg::GetNodeIP { index: 2 }).unwrap();
+        let result: HandleResponse = from_binary(&query_res).unwrap();
+        let ip: NodeEndpoint = from_binary(&result.data.unwrap()).unwrap();
+        assert_eq!(ip, NodeEndpoint::Ipv4 { address: String::from("192.168.0.1"), port: Some(5000) });
+    }
+
+    #[test]
+    fn node_pagination_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in ["anyone", "alice", "bob", "charlie", "dave"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // Walk the list two at a time
+        let mut seen = vec![];
+        let mut start_after = None;
+        loop {
+            let query_res = query(&deps, QueryMsg::GetNodeList { start_after: start_after.clone(), limit: Some(2), min_score: None }).unwrap();
+            let value: NodeListResponse = from_binary(&query_res).unwrap();
+            assert!(value.nodes.len() <= 2);
+            match value.nodes.last() {
+                Some(node) => start_after = Some(node.ip.clone()),
+                None => break,
+            }
+            seen.extend(value.nodes.into_iter().map(|node| node.address));
+        }
+        assert_eq!(seen, vec!["anyone", "alice", "bob", "charlie", "dave"]);
+
+        // Limits past the hard maximum are cut down
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { max_node_list_size: Some(3), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetNodeList { start_after: None, limit: Some(1000), min_score: None }).unwrap();
+        let value: NodeListResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.nodes.len(), 3);
+
+        let res = query(&deps, QueryMsg::GetNodeList { start_after: Some(String::from("10.9.9.9")), limit: None, min_score: None });
+        assert!(res.is_err());
+
+        let query_res = query(&deps, QueryMsg::GetNode { ip: String::from("192.168.0.2") }).unwrap();
+        let value: NodeInfo = from_binary(&query_res).unwrap();
+        assert_eq!(value.address, "bob");
+        assert_eq!(value.score, 500);
+
+        let res = query(&deps, QueryMsg::GetNode { ip: String::from("192.168.0.9") });
+        match res {
+            Err(StdError::NotFound { .. }) => {}
+            _ => panic!("Expected NotFound"),
+        }
+
+        let res = query(&deps, QueryMsg::GetNodeIP { index: 5 });
+        match res {
+            Err(StdError::NotFound { .. }) => {}
+            _ => panic!("Expected NotFound"),
+        }
+    }
+
+    #[test]
+    fn escrow_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { replication_factor: Some(2), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        // Payments must be in the payment denom and can't go to folders
+        let env = mock_env("anyone", &coins(1200, "token"));
+        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/a.txt"), pkey: String::from("a"), skey: String::from("test")};
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: String::from("anyone/docs") }).unwrap();
+
+        let env = mock_env("anyone", &coins(1200, "uscrt"));
+        let msg = HandleMsg::Create { contents: String::from("{}"), path: String::from("anyone/docs"), pkey: String::from("b"), skey: String::from("test")};
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        // Block 12345 is in epoch 12, so the payment covers epochs 12 to 23
+        let env = mock_env("anyone", &coins(1200, "uscrt"));
+        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/a.txt"), pkey: String::from("a"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/a.txt") }).unwrap();
+        let value: Escrow = from_binary(&query_res).unwrap();
+        assert_eq!(value, Escrow { uploader: String::from("anyone"), denom: String::from("uscrt"), balance: Uint128(1200), next_epoch: 12, end_epoch: 24 });
+
+        for (i, address) in ["bob", "alice", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // bob and alice prove this file in epochs 12 and 13, but alice is slashed in 13.
+        // charlie only proves some other file, which doesn't earn him anything from this one.
+        let key = "anyone0/anyone/a.txt";
+        for epoch in [12, 13].iter() {
+            record_file_proof(&mut deps.storage, *epoch, key, "bob").unwrap();
+            record_file_proof(&mut deps.storage, *epoch, key, "alice").unwrap();
+            record_proof(&mut deps.storage, "charlie", *epoch).unwrap();
+            record_file_proof(&mut deps.storage, *epoch, "anyone0/anyone/other.txt", "charlie").unwrap();
+        }
+        let mut env = mock_env("creator", &[]);
+        env.block.height = 13_100;
+        let _res = handle(&mut deps, env, HandleMsg::SlashNode { address: HumanAddr::from("alice"), percent: 10 }).unwrap();
+
+        // Epoch 13 isn't over yet
+        let mut env = mock_env("bob", &[]);
+        env.block.height = 13_999;
+        let _res = handle(&mut deps, env, HandleMsg::SettleEscrow { path: String::from("anyone/a.txt") }).unwrap();
+        let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from("alice") }).unwrap();
+        let value: Vec<Coin> = from_binary(&query_res).unwrap();
+        assert_eq!(value, coins(50, "uscrt"));
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height = 14_000;
+        let _res = handle(&mut deps, env, HandleMsg::SettleEscrow { path: String::from("anyone/a.txt") }).unwrap();
+
+        for (address, balance) in [("bob", 100), ("alice", 50), ("anyone", 50)].iter() {
+            let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from(*address) }).unwrap();
+            let value: Vec<Coin> = from_binary(&query_res).unwrap();
+            assert_eq!(value, coins(*balance, "uscrt"));
+        }
+        let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from("charlie") }).unwrap();
+        let value: Vec<Coin> = from_binary(&query_res).unwrap();
+        assert!(value.is_empty());
+
+        // The escrow follows the file when it moves, along with what was proven in the current epoch
+        record_file_proof(&mut deps.storage, 14, key, "bob").unwrap();
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 14_500;
+        let msg = HandleMsg::Move { old_path: String::from("anyone/a.txt"), new_path: String::from("anyone/b.txt") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        assert!(query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/a.txt") }).is_err());
+        let query_res = query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/b.txt") }).unwrap();
+        let value: Escrow = from_binary(&query_res).unwrap();
+        assert_eq!(value.balance, Uint128(1000));
+        assert_eq!(value.next_epoch, 14);
+
+        let mut env = mock_env("bob", &[]);
+        env.block.height = 15_000;
+        let _res = handle(&mut deps, env, HandleMsg::SettleEscrow { path: String::from("anyone/b.txt") }).unwrap();
+
+        // Deleting the file refunds the rest to the uploader
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 15_500;
+        let _res = handle(&mut deps, env, HandleMsg::Remove { path: String::from("anyone/b.txt") }).unwrap();
+
+        assert!(query(&deps, QueryMsg::GetEscrow { path: String::from("anyone/b.txt") }).is_err());
+        let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from("anyone") }).unwrap();
+        let value: Vec<Coin> = from_binary(&query_res).unwrap();
+        assert_eq!(value, coins(50 + 50 + 900, "uscrt"));
+
+        // So does ForgetMe, for every escrow in the old namespace
+        let env = mock_env("anyone", &coins(600, "uscrt"));
+        let msg = HandleMsg::Create { contents: String::from("I'm sad"), path: String::from("anyone/c.txt"), pkey: String::from("c"), skey: String::from("test")};
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::ForgetMe {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from("anyone") }).unwrap();
+        let value: Vec<Coin> = from_binary(&query_res).unwrap();
+        assert_eq!(value, coins(1000, "uscrt"));
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::SweepForgotten { address: HumanAddr::from("anyone"), limit: 100 }).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetEscrowBalance { address: HumanAddr::from("anyone") }).unwrap();
+        let value: Vec<Coin> = from_binary(&query_res).unwrap();
+        assert_eq!(value, coins(1600, "uscrt"));
+
+        // Payouts stay in the denom they were paid in
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { payment_denom: Some(String::from("token")), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("bob", &[]);
+        let res = handle(&mut deps, env, HandleMsg::WithdrawEscrow {}).unwrap();
+        assert_eq!(res.messages, vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: HumanAddr::from("cosmos2contract"),
+            to_address: HumanAddr::from("bob"),
+            amount: coins(150, "uscrt"),
+        })]);
+
+        let env = mock_env("bob", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::WithdrawEscrow {}).is_err());
+    }
+
+    #[test]
+    fn epoch_rewards_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let _vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for (i, address) in ["bob", "alice", "charlie"].iter().enumerate() {
+            let env = mock_env(address.to_string(), &coins(1_000_000, "uscrt"));
+            let msg = HandleMsg::InitNode { ip: format!("192.168.0.{}", i), address: address.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        set_node_score(&mut deps.storage, String::from("192.168.0.1"), 1000);
+
+        // bob proves twice at 500, alice once at 1000, charlie is slashed in the same epoch
+        record_proof(&mut deps.storage, "bob", 12).unwrap();
+        record_proof(&mut deps.storage, "bob", 12).unwrap();
+        record_proof(&mut deps.storage, "alice", 12).unwrap();
+        record_proof(&mut deps.storage, "charlie", 12).unwrap();
+        let mut env = mock_env("creator", &[]);
+        env.block.height = 12_500;
+        let _res = handle(&mut deps, env, HandleMsg::SlashNode { address: HumanAddr::from("charlie"), percent: 10 }).unwrap();
+
+        // Block 12345 is still in epoch 12
+        let env = mock_env("anyone", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 10 }).is_err());
+
+        // Three provers take three steps to weigh and three to pay, two at a time here
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 13_000;
+        let res = handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 2 }).unwrap();
+        assert_eq!(res.log[0].value, "12");
+        assert_eq!(res.log[1].value, "0");
+        assert_eq!(res.log[2].value, "4");
+
+        // Raising the emission halfway doesn't change what this epoch pays
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::UpdateConfig { config: ConfigUpdate { epoch_emission: Some(5000), ..ConfigUpdate::default() } };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 13_000;
+        let res = handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 2 }).unwrap();
+        assert_eq!(res.log[1].value, "500");
+        assert_eq!(res.log[2].value, "2");
+
+        // The epoch isn't paid until its last prover is
+        let query_res = query(&deps, QueryMsg::GetEpochPayouts { address: HumanAddr::from("bob") }).unwrap();
+        let value: EpochPayoutsResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.next_epoch, None);
+
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 13_000;
+        let res = handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 2 }).unwrap();
+        assert_eq!(res.log[1].value, "500");
+        assert_eq!(res.log[2].value, "0");
+
+        for (address, expected) in [("bob", 500), ("alice", 500), ("charlie", 0)].iter() {
+            let query_res: Binary = query(&deps, QueryMsg::GetNodeCoins { address: address.to_string() }).unwrap();
+            let result: HandleResponse = from_binary(&query_res).unwrap();
+            let coins: u32 = from_binary(&result.data.unwrap()).unwrap();
+            assert_eq!(coins, *expected);
+        }
+
+        // Each epoch is paid once
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 13_000;
+        assert!(handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 10 }).is_err());
+
+        // An epoch nobody proved anything in pays nothing
+        let mut env = mock_env("anyone", &[]);
+        env.block.height = 15_000;
+        let res = handle(&mut deps, env, HandleMsg::DistributeEpoch { limit: 10 }).unwrap();
+        assert_eq!(res.log[1].value, "0");
+
+        let query_res = query(&deps, QueryMsg::GetEpochPayouts { address: HumanAddr::from("bob") }).unwrap();
+        let value: EpochPayoutsResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value, EpochPayoutsResponse {
+            next_epoch: Some(14),
+            payouts: vec![EpochPayout { epoch: 12, proofs: 2, score: 500, credits: 500 }],
+        });
+
+        let query_res = query(&deps, QueryMsg::GetEpochPayouts { address: HumanAddr::from("charlie") }).unwrap();
+        let value: EpochPayoutsResponse = from_binary(&query_res).unwrap();
+        assert!(value.payouts.is_empty());
+
+        // Credits that would overflow are refused rather than panicking
+        let res = add_coins(&mut deps.storage, "bob", u32::MAX);
+        assert_eq!(res.unwrap_err().to_string(), format!("Generic error: An address can't hold more than {} reward credits", u32::MAX));
+        assert_eq!(add_coins(&mut deps.storage, "bob", 0).unwrap(), 500);
+    }
+
+    #[test]
+    fn manifest_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("bob", &coins(1_000_000, "uscrt"));
+        let msg = HandleMsg::InitNode { ip: String::from("192.168.0.1"), address: String::from("bob") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CreateManifest { path: String::from("anyone/movie.mp4"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let chunk = |byte: u8, size: u64, nodes: &[&str]| NewChunk {
+            hash: Binary(vec![byte; 32]),
+            size,
+            nodes: nodes.iter().map(|node| HumanAddr::from(*node)).collect(),
+        };
+
+        // One bad chunk rejects the whole append
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![chunk(1, 1000, &["bob"]), chunk(2, 500, &["alice"])] };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![NewChunk { hash: Binary(vec![1; 31]), size: 1000, nodes: vec![] }] };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![chunk(1, 1000, &["bob"])] };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        // Appends can span transactions
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![chunk(1, 1000, &["bob"])] };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![chunk(2, 500, &[])] };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::FinalizeManifest { path: String::from("anyone/movie.mp4"), chunk_count: 2, total_size: 1000, digest: None };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::FinalizeManifest { path: String::from("anyone/movie.mp4"), chunk_count: 2, total_size: 1500, digest: Some(Binary(vec![9; 32])) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![chunk(3, 10, &[])] };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        // The manifest moves with the file
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/movie.mp4"), new_path: String::from("anyone/film.mp4") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::VerifyContent { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string(), digest: Binary(vec![9; 32]) }).unwrap();
+        let value: VerifyContentResponse = from_binary(&query_res).unwrap();
+        assert!(value.matches);
+
+        let query_res = query(&deps, QueryMsg::GetManifest { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string(), start: 1, limit: 10 }).unwrap();
+        let value: ManifestResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value, ManifestResponse {
+            chunk_count: 2,
+            total_size: 1500,
+            finalized: true,
+            digest: Some(Binary(vec![9; 32])),
+            chunks: vec![ChunkDescriptor { hash: Binary(vec![2; 32]), size: 500, offset: 1000, nodes: vec![] }],
+        });
+
+        // A node that leaves drops out of the chunks it was listed on
+        let query_res = query(&deps, QueryMsg::GetManifest { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string(), start: 0, limit: 1 }).unwrap();
+        let value: ManifestResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.chunks[0].nodes, vec![HumanAddr::from("bob")]);
+
+        let env = mock_env("bob", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RemoveNode {}).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetManifest { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string(), start: 0, limit: 1 }).unwrap();
+        let value: ManifestResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.chunks[0].nodes, Vec::<HumanAddr>::new());
+
+        // Writing plain contents over it drops the manifest
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("trailer"), path: String::from("anyone/film.mp4"), pkey: String::from("b"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let res = query(&deps, QueryMsg::GetManifest { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string(), start: 0, limit: 10 });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn upload_session_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+        let bob_vk = init_for_test(&mut deps, String::from("bob"));
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::BeginUpload { path: String::from("anyone/big.txt"), total_size: 11, chunk_count: 3 };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BeginUpload { path: String::from("anyone/big.txt"), total_size: 64 * 1024 + 1, chunk_count: 3 };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BeginUpload { path: String::from("anyone/big.txt"), total_size: 11, chunk_count: 3 };
+        let res = handle(&mut deps, env, msg).unwrap();
+        let session = match from_binary(&res.data.unwrap()).unwrap() {
+            HandleAnswer::UploadSession { session } => session,
+            _ => panic!("Unexpected result from handle"),
+        };
+
+        // Chunks can arrive in any order and be sent again
+        for (index, data) in [(2, "ld"), (0, "hello"), (0, "HELLO")].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::UploadChunk { session, index: *index, data: data.to_string() };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        let env = mock_env("bob", &[]);
+        let msg = HandleMsg::UploadChunk { session, index: 1, data: String::from(" wor") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::UploadChunk { session, index: 3, data: String::from(" wor") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::UploadChunk { session, index: 1, data: String::from(" world") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CommitUpload { session, pkey: String::from("a"), skey: String::from("test") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::UploadChunk { session, index: 1, data: String::from(" wor") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("anyone"), session, key: vk.to_string() }).unwrap();
+        let value: UploadSession = from_binary(&query_res).unwrap();
+        assert_eq!(value.received, vec![true, true, true]);
+        assert_eq!(value.received_size, 11);
+
+        // Only the owner sees a session, anyone else gets the same answer as for a missing one
+        let res = query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("anyone"), session, key: String::from("wrong") });
+        assert!(res.is_err());
+        let res = query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("bob"), session, key: bob_vk.to_string() });
+        let missing = query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("bob"), session: session + 100, key: bob_vk.to_string() });
+        assert_eq!(res.unwrap_err().to_string(), missing.unwrap_err().to_string());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CommitUpload { session, pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/big.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "HELLO world");
+        assert!(query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("anyone"), session, key: vk.to_string() }).is_err());
+
+        // A session left alone for upload_window blocks expires, but can still be aborted
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::BeginUpload { path: String::from("anyone/other.txt"), total_size: 5, chunk_count: 1 };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let mut env = mock_env("anyone", &[]);
+        env.block.height += 601;
+        let msg = HandleMsg::UploadChunk { session: session + 1, index: 0, data: String::from("hello") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::AbortUpload { session: session + 1 }).unwrap();
+        assert!(query(&deps, QueryMsg::GetUploadSession { behalf: HumanAddr::from("anyone"), session: session + 1, key: vk.to_string() }).is_err());
+    }
+
+    #[test]
+    fn content_dedup_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+        let hash = content_hash("same bytes");
+
+        for path in ["anyone/a.txt", "anyone/b.txt"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("same bytes"), path: String::from(*path), pkey: String::from("a"), skey: String::from("test") };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+        assert_eq!(body_references(&deps.storage, &hash), 2);
+
+        // Permission changes rewrite the file but not its contents
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AllowRead { path: String::from("anyone/a.txt"), address_list: vec![String::from("alice")] };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(body_references(&deps.storage, &hash), 2);
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::Remove { path: String::from("anyone/a.txt") }).unwrap();
+        assert_eq!(body_references(&deps.storage, &hash), 1);
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/b.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "same bytes");
+
+        // Overwriting the last reference frees the old body
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("new bytes"), path: String::from("anyone/b.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(body_references(&deps.storage, &hash), 0);
+        assert_eq!(body_references(&deps.storage, &content_hash("new bytes")), 1);
+    }
+
+    #[test]
+    fn content_digest_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+        let vk2 = init_for_test(&mut deps, String::from("alice"));
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("checked"), path: String::from("anyone/sum.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/sum.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.digest, content_digest("checked"));
+        assert_eq!(value.digest.as_slice().len(), 32);
+
+        for (guess, expected) in [("checked", true), ("tampered", false)].iter() {
+            let query_res = query(&deps, QueryMsg::VerifyContent { behalf: HumanAddr::from("anyone"), path: String::from("anyone/sum.txt"), key: vk.to_string(), digest: content_digest(guess) }).unwrap();
+            let value: VerifyContentResponse = from_binary(&query_res).unwrap();
+            assert_eq!(value.matches, *expected);
+        }
+
+        // Someone who can't read the file can't test guesses against it either
+        let res = query(&deps, QueryMsg::VerifyContent { behalf: HumanAddr::from("alice"), path: String::from("anyone/sum.txt"), key: vk2.to_string(), digest: content_digest("checked") });
+        assert!(res.is_err());
+
+        // A new write replaces the digest
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("tampered"), path: String::from("anyone/sum.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/sum.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.digest, content_digest("tampered"));
+    }
+
+    #[test]
+    fn namespace_root_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        for path in ["anyone/a.txt", "anyone/b.txt", "anyone/c.txt", "anyone/d.txt"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: path.to_string(), path: path.to_string(), pkey: String::from("a"), skey: String::from("test") };
+            let _res = handle(&mut deps, env, msg).unwrap();
+        }
+
+        // Every proof checks out offline, and the leaves rebuild the same root from scratch
+        let check = |deps: &Extern<_, _, _>, paths: &[&str]| -> NamespaceTree {
+            let query_res = query(deps, QueryMsg::GetNamespaceRoot { address: HumanAddr::from("anyone") }).unwrap();
+            let tree: NamespaceTree = from_binary(&query_res).unwrap();
+            assert_eq!(tree.leaf_count, paths.len() as u64);
+
+            let mut leaves = vec![[0u8; 32]; paths.len()];
+            for path in paths {
+                let query_res = query(deps, QueryMsg::GetNamespaceProof { behalf: HumanAddr::from("anyone"), path: path.to_string(), key: vk.to_string() }).unwrap();
+                let value: NamespaceProofResponse = from_binary(&query_res).unwrap();
+                assert_eq!(value.root, tree.root);
+                assert!(verify_namespace_proof(tree.root.as_slice(), path, value.digest.as_slice(), value.index, value.leaf_count, &value.proof));
+                assert!(!verify_namespace_proof(tree.root.as_slice(), path, content_digest("forged").as_slice(), value.index, value.leaf_count, &value.proof));
+                leaves[value.index as usize] = namespace_leaf(path, value.digest.as_slice());
+            }
+            assert_eq!(merkle_root(&leaves).to_vec(), tree.root.0);
+            tree
+        };
+        let tree = check(&deps, &["anyone", "anyone/a.txt", "anyone/b.txt", "anyone/c.txt", "anyone/d.txt"]);
+
+        // Permission changes leave the root alone
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AllowRead { path: String::from("anyone/c.txt"), address_list: vec![String::from("alice")] };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(check(&deps, &["anyone", "anyone/a.txt", "anyone/b.txt", "anyone/c.txt", "anyone/d.txt"]), tree);
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::Remove { path: String::from("anyone/b.txt") }).unwrap();
+        let after_remove = check(&deps, &["anyone", "anyone/a.txt", "anyone/c.txt", "anyone/d.txt"]);
+        assert_ne!(after_remove.root, tree.root);
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/a.txt"), new_path: String::from("anyone/e.txt") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        check(&deps, &["anyone", "anyone/c.txt", "anyone/d.txt", "anyone/e.txt"]);
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("changed"), path: String::from("anyone/d.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        check(&deps, &["anyone", "anyone/c.txt", "anyone/d.txt", "anyone/e.txt"]);
+
+        let res = query(&deps, QueryMsg::GetNamespaceProof { behalf: HumanAddr::from("anyone"), path: String::from("anyone/b.txt"), key: vk.to_string() });
+        assert!(res.is_err());
+
+        // A finalized manifest's digest replaces its empty contents in the leaf, and follows it on Move
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CreateManifest { path: String::from("anyone/movie.mp4"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::AppendChunks { path: String::from("anyone/movie.mp4"), chunks: vec![NewChunk { hash: Binary(vec![1; 32]), size: 10, nodes: vec![] }] };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::FinalizeManifest { path: String::from("anyone/movie.mp4"), chunk_count: 1, total_size: 10, digest: Some(Binary(vec![9; 32])) };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        let before_move = check(&deps, &["anyone", "anyone/c.txt", "anyone/d.txt", "anyone/e.txt", "anyone/movie.mp4"]);
+
+        let query_res = query(&deps, QueryMsg::GetNamespaceProof { behalf: HumanAddr::from("anyone"), path: String::from("anyone/movie.mp4"), key: vk.to_string() }).unwrap();
+        let value: NamespaceProofResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.digest, Binary(vec![9; 32]));
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/movie.mp4"), new_path: String::from("anyone/film.mp4") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+        check(&deps, &["anyone", "anyone/c.txt", "anyone/d.txt", "anyone/e.txt", "anyone/film.mp4"]);
+
+        let query_res = query(&deps, QueryMsg::GetNamespaceProof { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string() }).unwrap();
+        let value: NamespaceProofResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.digest, Binary(vec![9; 32]));
+
+        // Recorded roots stay tied to their height, so a proof taken then still checks out after the tree changes
+        let query_res = query(&deps, QueryMsg::GetNamespaceProof { behalf: HumanAddr::from("anyone"), path: String::from("anyone/film.mp4"), key: vk.to_string() }).unwrap();
+        let film: NamespaceProofResponse = from_binary(&query_res).unwrap();
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::RecordNamespaceRoot {}).unwrap();
+
+        let mut env = mock_env("anyone", &[]);
+        env.block.height += 100;
+        let _res = handle(&mut deps, env, HandleMsg::Remove { path: String::from("anyone/film.mp4") }).unwrap();
+        let mut env = mock_env("anyone", &[]);
+        env.block.height += 100;
+        let _res = handle(&mut deps, env, HandleMsg::RecordNamespaceRoot {}).unwrap();
+
+        let snapshot = |height: u64| query(&deps, QueryMsg::GetNamespaceSnapshot { address: HumanAddr::from("anyone"), height });
+        assert!(snapshot(12344).is_err());
+        for height in [12345, 12444].iter() {
+            let value: NamespaceSnapshot = from_binary(&snapshot(*height).unwrap()).unwrap();
+            assert_eq!(value, NamespaceSnapshot { height: 12345, root: film.root.clone(), leaf_count: 5 });
+            assert_ne!(value.root, before_move.root);
+            assert!(verify_namespace_proof(value.root.as_slice(), "anyone/film.mp4", &[9; 32], film.index, value.leaf_count, &film.proof));
+        }
+        let value: NamespaceSnapshot = from_binary(&snapshot(20000).unwrap()).unwrap();
+        assert_eq!(value.height, 12445);
+        assert_eq!(value.leaf_count, 4);
+    }
+
+    #[test]
+    fn folder_type_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        // The trailing '/' is optional
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: String::from("anyone/docs") }).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/docs/"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file, make_folder("anyone"));
+        assert!(value.file.is_folder());
+
+        let env = mock_env("anyone", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::CreateFolder { path: String::from("anyone/docs/") }).is_err());
+
+        // Create only makes files, and a file can't share a folder's name or the other way round
+        for path in ["anyone/docs/", "anyone/docs"].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("text"), path: path.to_string(), pkey: String::from("a"), skey: String::from("test") };
+            assert!(handle(&mut deps, env, msg).is_err());
+        }
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("text"), path: String::from("anyone/a.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::CreateFolder { path: String::from("anyone/a.txt/") }).is_err());
+
+        // Nothing goes under a file
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("text"), path: String::from("anyone/a.txt/b.txt"), pkey: String::from("a"), skey: String::from("test") };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        // Moves keep the type, and nothing can be moved over a folder or turn a file into one
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/a.txt"), new_path: String::from("anyone/docs/") };
+        assert_eq!(handle(&mut deps, env, msg).unwrap_err().to_string(), "Generic error: File move unsuccessful. A folder with this name already exists");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/docs"), new_path: String::from("anyone/a.txt") };
+        assert_eq!(handle(&mut deps, env, msg).unwrap_err().to_string(), "Generic error: File move unsuccessful. A file with this name already exists");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/docs/"), new_path: String::from("anyone/papers/") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("text"), path: String::from("anyone/papers/c.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/papers/"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert!(value.file.is_folder());
+
+        // A folder can't go inside itself, and one with something in it can't be moved or removed
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/papers/"), new_path: String::from("anyone/papers/old/") };
+        assert_eq!(handle(&mut deps, env, msg).unwrap_err().to_string(), "Generic error: A folder can't be moved into itself");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/papers/"), new_path: String::from("anyone/archive/") };
+        assert_eq!(handle(&mut deps, env, msg).unwrap_err().to_string(), "Generic error: This folder isn't empty. Move or remove what is in it first");
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Remove { path: String::from("anyone/papers/") };
+        assert_eq!(handle(&mut deps, env, msg).unwrap_err().to_string(), "Generic error: This folder isn't empty. Move or remove what is in it first");
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/papers/c.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "text");
+        assert!(query(&deps, QueryMsg::GetContents { path: String::from("anyone/archive/"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).is_err());
+
+        // Once emptied it can be removed
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Move { old_path: String::from("anyone/papers/c.txt"), new_path: String::from("anyone/c.txt") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::Remove { path: String::from("anyone/papers/") }).unwrap();
+        assert!(query(&deps, QueryMsg::GetContents { path: String::from("anyone/papers/"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).is_err());
+    }
+
+    #[test]
+    fn path_canonical_test() {
+        assert_eq!(canonical_path("anyone//docs/./a.txt").unwrap(), "anyone/docs/a.txt");
+        assert_eq!(canonical_path("/anyone/docs//").unwrap(), "anyone/docs");
+        assert_eq!(canonical_path("anyone").unwrap(), "anyone");
+        assert_eq!(parent_path("anyone/docs/a.txt"), Some("anyone/docs"));
+        assert_eq!(parent_path("anyone"), None);
+        assert!(canonical_path("").is_err());
+        assert!(canonical_path("//./").is_err());
+        assert!(canonical_path("anyone/../alice/a.txt").is_err());
+        assert!(canonical_path("anyone/bad\u{7}name").is_err());
+        assert!(canonical_path("anyone/back\\slash").is_err());
+        assert!(canonical_path(&format!("anyone/{}", "x".repeat(MAX_SEGMENT_LENGTH + 1))).is_err());
+        assert!(canonical_path(&format!("anyone{}", "/x".repeat(MAX_PATH_DEPTH))).is_err());
+        assert!(canonical_path(&format!("anyone{}", "/x".repeat(MAX_PATH_DEPTH - 1))).is_ok());
+
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+
+        let env = mock_env("anyone", &[]);
+        let _res = handle(&mut deps, env, HandleMsg::CreateFolder { path: String::from("anyone//docs/.") }).unwrap();
+
+        // Spellings of the same path all reach the same file
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("one"), path: String::from("anyone/docs//a.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::Create { contents: String::from("two"), path: String::from("/anyone/./docs/a.txt"), pkey: String::from("a"), skey: String::from("test") };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/docs/a.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "two");
+
+        // Bad paths are refused before anything is written
+        for path in ["anyone/../alice/a.txt", "anyone/docs/\u{0}.txt", ""].iter() {
+            let env = mock_env("anyone", &[]);
+            let msg = HandleMsg::Create { contents: String::from("bad"), path: path.to_string(), pkey: String::from("a"), skey: String::from("test") };
+            assert!(handle(&mut deps, env, msg).is_err());
+        }
+        let res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/docs/../a.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() });
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn batch_test() {
+        let mut deps = mock_dependencies(20, &[]);
+        let vk = init_for_test(&mut deps, String::from("anyone"));
+        let vk2 = init_for_test(&mut deps, String::from("alice"));
+
+        let ops = vec![
+            FileOp::Create { path: String::from("anyone/a.txt"), contents: String::from("one"), pkey: String::from("a"), skey: String::from("test") },
+            FileOp::Copy { from_path: String::from("anyone/a.txt"), to_path: String::from("anyone//b.txt") },
+            FileOp::Update { path: String::from("anyone/b.txt"), contents: String::from("two") },
+            FileOp::Move { old_path: String::from("anyone/a.txt"), new_path: String::from("anyone/c.txt") },
+            FileOp::SetPermission { path: String::from("anyone/c.txt"), change: PermissionChange::AllowRead, address_list: vec!(String::from("alice")) },
+            FileOp::Remove { path: String::from("anyone/b.txt") },
+        ];
+        let env = mock_env("anyone", &[]);
+        let res = handle(&mut deps, env, HandleMsg::Batch { ops }).unwrap();
+        let results = match from_binary(&res.data.unwrap()).unwrap() {
+            HandleAnswer::Batch { results } => results,
+            _ => panic!("Unexpected answer"),
+        };
+        assert_eq!(results, vec![
+            FileOpResult::Created { path: String::from("anyone/a.txt"), digest: content_digest("one") },
+            FileOpResult::Copied { path: String::from("anyone/b.txt"), digest: content_digest("one") },
+            FileOpResult::Updated { path: String::from("anyone/b.txt"), digest: content_digest("two") },
+            FileOpResult::Moved { path: String::from("anyone/c.txt") },
+            FileOpResult::PermissionSet { path: String::from("anyone/c.txt") },
+            FileOpResult::Removed { path: String::from("anyone/b.txt") },
+        ]);
+
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/c.txt"), behalf: HumanAddr::from("alice"), key: vk2.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "one");
+        let res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/b.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() });
+        assert!(res.is_err());
+
+        // A failing op fails the whole message, and the error says which one
+        let ops = vec![
+            FileOp::Create { path: String::from("anyone/d.txt"), contents: String::from("three"), pkey: String::from("a"), skey: String::from("test") },
+            FileOp::Remove { path: String::from("anyone/missing.txt") },
+        ];
+        let env = mock_env("anyone", &[]);
+        let err = handle(&mut deps, env, HandleMsg::Batch { ops }).unwrap_err();
+        assert_eq!(err.to_string(), "Generic error: Operation 1 failed: This path does not exist. Cannot remove. not found");
+
+        // Alice can read the file but not update it
+        let env = mock_env("alice", &[]);
+        let ops = vec![FileOp::Update { path: String::from("anyone/c.txt"), contents: String::from("mine") }];
+        assert!(handle(&mut deps, env, HandleMsg::Batch { ops }).is_err());
+
+        // Checked before anything runs
+        let env = mock_env("anyone", &[]);
+        assert!(handle(&mut deps, env, HandleMsg::Batch { ops: vec![] }).is_err());
+
+        // The ops before a bad one haven't run yet, so nothing is written. Mock storage doesn't roll back like
+        // the chain does, so this only holds because validation comes first.
+        let env = mock_env("anyone", &[]);
+        let ops = vec![
+            FileOp::Create { path: String::from("anyone/e.txt"), contents: String::from("x"), pkey: String::from("a"), skey: String::from("test") },
+            FileOp::Create { path: String::from("anyone/f.txt"), contents: "x".repeat(64 * 1024 + 1), pkey: String::from("a"), skey: String::from("test") },
+        ];
+        let err = handle(&mut deps, env, HandleMsg::Batch { ops }).unwrap_err();
+        assert_eq!(err.to_string(), "Generic error: Operation 1: file contents are larger than the 65536 byte limit");
+        let res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/e.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() });
+        assert!(res.is_err());
+
+        let env = mock_env("anyone", &[]);
+        let ops = vec![
+            FileOp::Remove { path: String::from("anyone/c.txt") },
+            FileOp::Update { path: String::from("anyone/c.txt"), contents: "x".repeat(64 * 1024 + 1) },
+        ];
+        let err = handle(&mut deps, env, HandleMsg::Batch { ops }).unwrap_err();
+        assert_eq!(err.to_string(), "Generic error: Operation 1: file contents are larger than the 65536 byte limit");
+        let query_res = query(&deps, QueryMsg::GetContents { path: String::from("anyone/c.txt"), behalf: HumanAddr::from("anyone"), key: vk.to_string() }).unwrap();
+        let value: FileResponse = from_binary(&query_res).unwrap();
+        assert_eq!(value.file.get_contents(), "one");
+
+        let env = mock_env("anyone", &coins(1_000, "uscrt"));
+        let ops = vec![FileOp::Remove { path: String::from("anyone/c.txt") }];
+        assert!(handle(&mut deps, env, HandleMsg::Batch { ops }).is_err());
+
+        // Permission changes in a batch follow the permission pause
+        let env = mock_env("creator", &[]);
+        let msg = HandleMsg::SetPause { file_writes: None, permission_changes: Some(true), node_registration: None, reward_claims: None };
+        let _res = handle(&mut deps, env, msg).unwrap();
+
+        let env = mock_env("anyone", &[]);
+        let ops = vec![FileOp::SetPermission { path: String::from("anyone/c.txt"), change: PermissionChange::ResetRead, address_list: vec![] }];
+        assert!(handle(&mut deps, env, HandleMsg::Batch { ops }).is_err());
+
+        // The multi handlers now refuse lists that don't line up
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::CreateMulti {
+            contents_list: vec!(String::from("x"), String::from("y")),
+            path_list: vec!(String::from("anyone/x.txt")),
+            pkey_list: vec!(String::from("a"), String::from("a")),
+            skey_list: vec!(String::from("test"), String::from("test")),
+        };
+        assert!(handle(&mut deps, env, msg).is_err());
+
+        let env = mock_env("anyone", &[]);
+        let msg = HandleMsg::MoveMulti { old_path_list: vec!(String::from("anyone/c.txt")), new_path_list: vec![] };
+        assert!(handle(&mut deps, env, msg).is_err());
+    }
 }
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_std::{StdError, StdResult};
+
+// Where a node can be reached. Nodes are keyed by the canonical string form from `Display`,
+// so the same endpoint written two different ways can't be registered twice.
+//   192.168.0.1:4000
+//   [2001:db8::1]:4000
+//   node1.example.com:4000
+//   /ip4/192.168.0.1/tcp/4000/p2p/Qm...
+// Ports are optional for everything except multiaddrs, which carry their own transport.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeEndpoint {
+    Ipv4 { address: String, port: Option<u16> },
+    Ipv6 { address: String, port: Option<u16> },
+    Hostname { host: String, port: Option<u16> },
+    Multiaddr { address: String },
+}
+
+pub const MAX_ENDPOINT_LEN: usize = 256;
+
+impl NodeEndpoint {
+    pub fn parse(input: &str) -> StdResult<NodeEndpoint> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(StdError::generic_err("Node endpoint is empty"));
+        }
+        if input.len() > MAX_ENDPOINT_LEN {
+            return Err(StdError::generic_err(format!("Node endpoint can be at most {} characters", MAX_ENDPOINT_LEN)));
+        }
+        if input.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(StdError::generic_err("Node endpoint can't contain whitespace"));
+        }
+
+        if input.starts_with('/') {
+            return parse_multiaddr(input);
+        }
+
+        // [v6]:port or [v6]
+        if let Some(rest) = input.strip_prefix('[') {
+            let close = rest.find(']').ok_or_else(|| StdError::generic_err("Missing ']' in IPv6 endpoint"))?;
+            let address = parse_ipv6(&rest[..close])?;
+            let port = match &rest[close + 1..] {
+                "" => None,
+                tail => match tail.strip_prefix(':') {
+                    Some(port) => Some(parse_port(port)?),
+                    None => return Err(StdError::generic_err("Expected ':port' after ']' in IPv6 endpoint")),
+                },
+            };
+            return Ok(NodeEndpoint::Ipv6 { address, port });
+        }
+
+        // A bare IPv6 address has more than one ':' and no port
+        if input.matches(':').count() > 1 {
+            return Ok(NodeEndpoint::Ipv6 { address: parse_ipv6(input)?, port: None });
+        }
+
+        let (host, port) = match input.rfind(':') {
+            Some(i) => (&input[..i], Some(parse_port(&input[i + 1..])?)),
+            None => (input, None),
+        };
+
+        if host.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            let address: Ipv4Addr = host
+                .parse()
+                .map_err(|_| StdError::generic_err(format!("'{}' is not a valid IPv4 address", host)))?;
+            return Ok(NodeEndpoint::Ipv4 { address: address.to_string(), port });
+        }
+
+        Ok(NodeEndpoint::Hostname { host: parse_hostname(host)?, port })
+    }
+
+    // The address or name the endpoint points at, without its port, transport or peer id
+    pub fn host(&self) -> &str {
+        match self {
+            NodeEndpoint::Ipv4 { address, .. } | NodeEndpoint::Ipv6 { address, .. } => address,
+            NodeEndpoint::Hostname { host, .. } => host,
+            // Canonical multiaddrs always start with /ip4/<address>, /ip6/<address> or /dns.../<host>
+            NodeEndpoint::Multiaddr { address } => address.split('/').nth(2).unwrap_or(address),
+        }
+    }
+}
+
+impl fmt::Display for NodeEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeEndpoint::Ipv4 { address, port: None } => write!(f, "{}", address),
+            NodeEndpoint::Ipv4 { address, port: Some(port) } => write!(f, "{}:{}", address, port),
+            NodeEndpoint::Ipv6 { address, port: None } => write!(f, "[{}]", address),
+            NodeEndpoint::Ipv6 { address, port: Some(port) } => write!(f, "[{}]:{}", address, port),
+            NodeEndpoint::Hostname { host, port: None } => write!(f, "{}", host),
+            NodeEndpoint::Hostname { host, port: Some(port) } => write!(f, "{}:{}", host, port),
+            NodeEndpoint::Multiaddr { address } => write!(f, "{}", address),
+        }
+    }
+}
+
+// Parses and returns the canonical form used as the node's key
+pub fn normalize_endpoint(input: &str) -> StdResult<String> {
+    Ok(NodeEndpoint::parse(input)?.to_string())
+}
+
+// Two nodes can't share a host, so one machine can't register again under another port or as a multiaddr
+pub fn endpoint_host(input: &str) -> StdResult<String> {
+    Ok(NodeEndpoint::parse(input)?.host().to_string())
+}
+
+// Plain digits only, so "080" and "+80" don't become other spellings of the same endpoint
+fn parse_port(port: &str) -> StdResult<u16> {
+    let invalid = || StdError::generic_err(format!("'{}' is not a valid port", port));
+    if port.is_empty() || port.starts_with('0') || !port.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    port.parse::<u16>().map_err(|_| invalid())
+}
+
+fn parse_ipv6(address: &str) -> StdResult<String> {
+    let address: Ipv6Addr = address
+        .parse()
+        .map_err(|_| StdError::generic_err(format!("'{}' is not a valid IPv6 address", address)))?;
+    Ok(address.to_string())
+}
+
+// RFC 1123 names with at least two labels. The last label can't be all digits so a mistyped IPv4
+// address isn't taken as a hostname.
+fn parse_hostname(host: &str) -> StdResult<String> {
+    let host = host.strip_suffix('.').unwrap_or(host).to_lowercase();
+    let invalid = || StdError::generic_err(format!("'{}' is not a valid hostname", host));
+
+    if host.len() > 253 {
+        return Err(invalid());
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < 2 || labels.last().map_or(true, |tld| tld.chars().all(|c| c.is_ascii_digit())) {
+        return Err(invalid());
+    }
+    for label in &labels {
+        if label.is_empty()
+            || label.len() > 63
+            || label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(invalid());
+        }
+    }
+
+    Ok(host)
+}
+
+// Only the protocols a storage node would be reached over. Each one is checked and rewritten in canonical form.
+fn parse_multiaddr(input: &str) -> StdResult<NodeEndpoint> {
+    let mut parts = input[1..].split('/');
+    let mut out = vec![];
+    let mut first = true;
+
+    while let Some(protocol) = parts.next() {
+        let protocol = protocol.to_lowercase();
+        let mut value = || {
+            parts
+                .next()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| StdError::generic_err(format!("Multiaddr protocol '{}' is missing its value", protocol)))
+        };
+
+        let component = match protocol.as_str() {
+            "ip4" => {
+                let address = value()?;
+                let address: Ipv4Addr = address
+                    .parse()
+                    .map_err(|_| StdError::generic_err(format!("'{}' is not a valid IPv4 address", address)))?;
+                format!("/ip4/{}", address)
+            }
+            "ip6" => format!("/ip6/{}", parse_ipv6(value()?)?),
+            "dns" | "dns4" | "dns6" | "dnsaddr" => {
+                let host = parse_hostname(value()?)?;
+                format!("/{}/{}", protocol, host)
+            }
+            "tcp" | "udp" if !first => format!("/{}/{}", protocol, parse_port(value()?)?),
+            "p2p" | "ipfs" if !first => {
+                let id = value()?;
+                if !id.chars().all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c)) {
+                    return Err(StdError::generic_err(format!("'{}' is not a valid peer id", id)));
+                }
+                format!("/p2p/{}", id)
+            }
+            "quic" | "quic-v1" | "ws" | "wss" | "tls" | "http" | "https" | "webtransport" if !first => {
+                format!("/{}", protocol)
+            }
+            "" => return Err(StdError::generic_err("Multiaddr has an empty component")),
+            _ if first => {
+                return Err(StdError::generic_err("Multiaddr must start with /ip4, /ip6 or /dns"))
+            }
+            _ => return Err(StdError::generic_err(format!("Unsupported multiaddr protocol '{}'", protocol))),
+        };
+
+        out.push(component);
+        first = false;
+    }
+
+    if out.len() < 2 {
+        return Err(StdError::generic_err("Multiaddr needs an address and a transport, e.g. /ip4/1.2.3.4/tcp/4000"));
+    }
+
+    Ok(NodeEndpoint::Multiaddr { address: out.concat() })
+}
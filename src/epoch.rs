@@ -0,0 +1,92 @@
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{StdResult, Storage};
+
+static PROOF_LOCATION: &[u8] = b"EPOCH_PROOFS";
+static SLASH_LOCATION: &[u8] = b"EPOCH_SLASHES";
+static PROVER_LOCATION: &[u8] = b"EPOCH_PROVERS";
+static PROVER_COUNT_LOCATION: &[u8] = b"EPOCH_PROVER_COUNT";
+static FILE_PROVER_LOCATION: &[u8] = b"EPOCH_FILE_PROVERS";
+
+// Blocks per epoch. Fixed rather than configurable, since changing it would renumber every epoch
+// and shift what has already been settled.
+pub const EPOCH_LENGTH: u64 = 1000;
+
+pub fn epoch_at(height: u64) -> u64 {
+    height / EPOCH_LENGTH
+}
+
+fn epoch_key(epoch: u64, address: &str) -> String {
+    format!("{}/{}", epoch, address)
+}
+
+// Counts the storage challenges a node passed during `epoch`. The first one also adds the node
+// to the epoch's provers, which is who DistributeEpoch pays. Provers are kept one per key so
+// DistributeEpoch can page through them.
+pub fn record_proof<S: Storage>(store: &mut S, address: &str, epoch: u64) -> StdResult<u64> {
+    let count = proof_count(store, address, epoch) + 1;
+    bucket(PROOF_LOCATION, store).save(epoch_key(epoch, address).as_bytes(), &count)?;
+
+    if count == 1 {
+        let index = prover_count(store, epoch);
+        bucket(PROVER_LOCATION, store).save(epoch_key(epoch, &index.to_string()).as_bytes(), &address.to_string())?;
+        bucket(PROVER_COUNT_LOCATION, store).save(epoch.to_string().as_bytes(), &(index + 1))?;
+    }
+
+    Ok(count)
+}
+
+pub fn prover_count<S: Storage>(store: &S, epoch: u64) -> u64 {
+    bucket_read(PROVER_COUNT_LOCATION, store)
+        .may_load(epoch.to_string().as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(0)
+}
+
+pub fn prover_at<S: Storage>(store: &S, epoch: u64, index: u64) -> StdResult<String> {
+    bucket_read(PROVER_LOCATION, store).load(epoch_key(epoch, &index.to_string()).as_bytes())
+}
+
+pub fn proof_count<S: Storage>(store: &S, address: &str, epoch: u64) -> u64 {
+    bucket_read(PROOF_LOCATION, store)
+        .may_load(epoch_key(epoch, address).as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(0)
+}
+
+// Nodes that passed a storage challenge on the file at `key` during `epoch`. This is what the file's
+// escrow pays for that epoch.
+pub fn file_provers<S: Storage>(store: &S, epoch: u64, key: &str) -> Vec<String> {
+    bucket_read(FILE_PROVER_LOCATION, store)
+        .may_load(epoch_key(epoch, key).as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+pub fn record_file_proof<S: Storage>(store: &mut S, epoch: u64, key: &str, address: &str) -> StdResult<()> {
+    let mut provers = file_provers(store, epoch, key);
+    if !provers.iter().any(|prover| prover == address) {
+        provers.push(address.to_string());
+        bucket(FILE_PROVER_LOCATION, store).save(epoch_key(epoch, key).as_bytes(), &provers)?;
+    }
+    Ok(())
+}
+
+// Carries the epoch's proofs over when a file moves, so the escrow that moves with it still sees them
+pub fn move_file_proofs<S: Storage>(store: &mut S, epoch: u64, from_key: &str, to_key: &str) -> StdResult<()> {
+    for address in file_provers(store, epoch, from_key) {
+        record_file_proof(store, epoch, to_key, &address)?;
+    }
+    bucket::<S, Vec<String>>(FILE_PROVER_LOCATION, store).remove(epoch_key(epoch, from_key).as_bytes());
+    Ok(())
+}
+
+pub fn record_slash<S: Storage>(store: &mut S, address: &str, epoch: u64) -> StdResult<()> {
+    bucket(SLASH_LOCATION, store).save(epoch_key(epoch, address).as_bytes(), &true)
+}
+
+pub fn was_slashed<S: Storage>(store: &S, address: &str, epoch: u64) -> bool {
+    bucket_read(SLASH_LOCATION, store)
+        .may_load(epoch_key(epoch, address).as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(false)
+}
@@ -0,0 +1,254 @@
+use std::cmp;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{
+    log, Api, BankMsg, Coin, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult,
+    Storage, Uint128,
+};
+
+use crate::backend::{file_key, get_namespace_from_path};
+use crate::epoch::{epoch_at, file_provers, move_file_proofs, was_slashed};
+use crate::state::{load, State, CONFIG_KEY};
+
+static ESCROW_LOCATION: &[u8] = b"ESCROWS";
+static PAYOUT_LOCATION: &[u8] = b"ESCROW_PAYOUTS";
+
+// Settling walks every covered epoch, so the length of an escrow is capped
+pub const MAX_ESCROW_EPOCHS: u64 = 1000;
+
+// Payment locked for one file, keyed by file_key. Every epoch from `next_epoch` up to `end_epoch`
+// releases an even share of what is left in `balance`. `denom` is what it was paid in, so a later
+// change to payment_denom doesn't change what it pays out.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Escrow {
+    pub uploader: String,
+    pub denom: String,
+    pub balance: Uint128,
+    pub next_epoch: u64,
+    pub end_epoch: u64,
+}
+
+fn load_escrow<S: Storage>(store: &S, key: &str) -> StdResult<Option<Escrow>> {
+    bucket_read(ESCROW_LOCATION, store).may_load(key.as_bytes())
+}
+
+fn save_escrow<S: Storage>(store: &mut S, key: &str, escrow: &Escrow) -> StdResult<()> {
+    bucket(ESCROW_LOCATION, store).save(key.as_bytes(), escrow)
+}
+
+// What an address can take out with WithdrawEscrow: node earnings and uploader refunds alike, one coin per denom
+pub fn load_payouts<S: Storage>(store: &S, address: &str) -> Vec<Coin> {
+    bucket_read(PAYOUT_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+fn credit<S: Storage>(store: &mut S, address: &str, denom: &str, amount: u128) -> StdResult<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let mut payouts = load_payouts(store, address);
+    match payouts.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount = Uint128(coin.amount.u128() + amount),
+        None => payouts.push(Coin { denom: denom.to_string(), amount: Uint128(amount) }),
+    }
+    bucket(PAYOUT_LOCATION, store).save(address.as_bytes(), &payouts)
+}
+
+fn sent_payment(state: &State, sent_funds: &[Coin]) -> StdResult<u128> {
+    let mut amount: u128 = 0;
+    for coin in sent_funds {
+        if coin.denom != state.config.payment_denom {
+            return Err(StdError::generic_err(format!("Storage payments must be sent in {}", state.config.payment_denom)));
+        }
+        amount += coin.amount.u128();
+    }
+    Ok(amount)
+}
+
+// Checks the funds sent with Create before anything is written. Returns 0 when nothing was sent.
+pub fn escrow_payment<S: Storage>(store: &S, sent_funds: &[Coin]) -> StdResult<u128> {
+    let state: State = load(store, CONFIG_KEY)?;
+    sent_payment(&state, sent_funds)
+}
+
+pub fn check_write_fee<S: Storage>(store: &S, payment: u128) -> StdResult<()> {
+    let state: State = load(store, CONFIG_KEY)?;
+    let fee = state.config.write_fee.u128();
+    if payment < fee {
+        return Err(StdError::generic_err(format!("Creating a file costs at least {}{}", fee, state.config.payment_denom)));
+    }
+    Ok(())
+}
+
+// Splits the funds sent with CreateMulti or Batch evenly over the `files` they create, with the remainder on
+// the first. Each share has to cover write_fee and is escrowed for its file like a Create payment.
+pub fn split_payment<S: Storage>(store: &S, sent_funds: &[Coin], files: usize) -> StdResult<Vec<u128>> {
+    let state: State = load(store, CONFIG_KEY)?;
+
+    let amount = sent_payment(&state, sent_funds)?;
+    if files == 0 {
+        if amount > 0 {
+            return Err(StdError::generic_err("Nothing here creates a file, so there is nothing to pay for"));
+        }
+        return Ok(vec![]);
+    }
+
+    let fee = state.config.write_fee.u128();
+    let share = amount / files as u128;
+    if share < fee {
+        return Err(StdError::generic_err(format!(
+            "Creating {} files costs at least {}{}",
+            files,
+            fee * files as u128,
+            state.config.payment_denom
+        )));
+    }
+    let mut shares = vec![share; files];
+    shares[0] += amount % files as u128;
+    Ok(shares)
+}
+
+// Locks `amount` for escrow_epochs epochs, starting with the current one.
+// Paying again for the same path closes the old escrow first.
+pub fn open_escrow<S: Storage>(store: &mut S, key: &str, uploader: &str, amount: u128, height: u64) -> StdResult<()> {
+    let state: State = load(store, CONFIG_KEY)?;
+    close_escrow(store, key, height)?;
+
+    let epoch = epoch_at(height);
+    let escrow = Escrow {
+        uploader: uploader.to_string(),
+        denom: state.config.payment_denom,
+        balance: Uint128(amount),
+        next_epoch: epoch,
+        end_epoch: epoch + state.config.escrow_epochs,
+    };
+    save_escrow(store, key, &escrow)
+}
+
+// Settles every finished epoch the escrow still covers. Each epoch's amount is split into replication_factor
+// shares, and each node that passed a storage challenge on this file in that epoch, and wasn't slashed in it,
+// gets one. If more nodes proved it than that, they split it evenly. Shares nobody earned go back to the uploader.
+fn settle<S: Storage>(store: &mut S, key: &str, escrow: &mut Escrow, height: u64) -> StdResult<u128> {
+    let last = cmp::min(epoch_at(height), escrow.end_epoch);
+    if escrow.next_epoch >= last {
+        return Ok(0);
+    }
+
+    let state: State = load(store, CONFIG_KEY)?;
+    let mut released: u128 = 0;
+    let mut refund: u128 = 0;
+    while escrow.next_epoch < last {
+        let epoch = escrow.next_epoch;
+        let amount = escrow.balance.u128() / (escrow.end_epoch - epoch) as u128;
+        let provers = file_provers(store, epoch, key);
+        let shares = cmp::max(provers.len() as u64, cmp::max(state.config.replication_factor, 1));
+        let share = amount / shares as u128;
+
+        let mut paid: u128 = 0;
+        for address in &provers {
+            if share > 0 && !was_slashed(store, address, epoch) {
+                credit(store, address, &escrow.denom, share)?;
+                paid += share;
+            }
+        }
+
+        released += paid;
+        refund += amount - paid;
+        escrow.balance = Uint128(escrow.balance.u128() - amount);
+        escrow.next_epoch += 1;
+    }
+    credit(store, &escrow.uploader, &escrow.denom, refund)?;
+
+    Ok(released)
+}
+
+// Called when the file goes away. Finished epochs are settled as usual and the rest is refunded.
+pub fn close_escrow<S: Storage>(store: &mut S, key: &str, height: u64) -> StdResult<Uint128> {
+    let mut escrow = match load_escrow(store, key)? {
+        Some(escrow) => escrow,
+        None => return Ok(Uint128(0)),
+    };
+
+    settle(store, key, &mut escrow, height)?;
+    credit(store, &escrow.uploader, &escrow.denom, escrow.balance.u128())?;
+    bucket::<S, Escrow>(ESCROW_LOCATION, store).remove(key.as_bytes());
+
+    Ok(escrow.balance)
+}
+
+// A moved file keeps its escrow. Anything paid for a file it replaces is closed out. Finished epochs are
+// settled first, since their proofs were recorded under the old path.
+pub fn move_escrow<S: Storage>(store: &mut S, from_key: &str, to_key: &str, height: u64) -> StdResult<()> {
+    close_escrow(store, to_key, height)?;
+    if let Some(mut escrow) = load_escrow(store, from_key)? {
+        settle(store, from_key, &mut escrow, height)?;
+        move_file_proofs(store, epoch_at(height), from_key, to_key)?;
+        save_escrow(store, to_key, &escrow)?;
+        bucket::<S, Escrow>(ESCROW_LOCATION, store).remove(from_key.as_bytes());
+    }
+    Ok(())
+}
+
+// Anyone can settle a file's escrow, so nodes don't depend on the uploader to get paid
+pub fn try_settle_escrow<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+) -> StdResult<HandleResponse> {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let key = file_key(&namespace, &path);
+    let mut escrow = load_escrow(&deps.storage, &key)?.ok_or_else(|| StdError::not_found("Escrow"))?;
+
+    let released = settle(&mut deps.storage, &key, &mut escrow, env.block.height)?;
+    save_escrow(&mut deps.storage, &key, &escrow)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("released", released), log("balance", escrow.balance)],
+        data: None,
+    })
+}
+
+// Pays out every denom the sender is owed, each in the denom it was escrowed in
+pub fn try_withdraw_escrow<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    let amount = load_payouts(&deps.storage, signer.as_str());
+    if amount.is_empty() {
+        return Err(StdError::generic_err("Nothing to withdraw"));
+    }
+    bucket::<S, Vec<Coin>>(PAYOUT_LOCATION, &mut deps.storage).remove(signer.as_str().as_bytes());
+
+    let withdrawn = amount.iter().map(|coin| format!("{}{}", coin.amount, coin.denom)).collect::<Vec<String>>().join(",");
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address,
+            to_address: signer,
+            amount,
+        })],
+        log: vec![log("withdrawn", withdrawn)],
+        data: None,
+    })
+}
+
+pub fn query_escrow<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    path: String,
+) -> StdResult<Escrow> {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    load_escrow(&deps.storage, &file_key(&namespace, &path))?.ok_or_else(|| StdError::not_found("Escrow"))
+}
+
+pub fn query_escrow_balance<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<Vec<Coin>> {
+    Ok(load_payouts(&deps.storage, address.as_str()))
+}
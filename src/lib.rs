@@ -4,6 +4,23 @@ pub mod state;
 pub mod backend;
 pub mod ordered_set;
 pub mod nodes;
+pub mod admin;
+pub mod reputation;
+pub mod challenge;
+pub mod merkle;
+pub mod rewards;
+pub mod staking;
+pub mod placement;
+pub mod selection;
+pub mod endpoint;
+pub mod epoch;
+pub mod escrow;
+pub mod manifest;
+pub mod upload;
+pub mod content;
+pub mod namespace_root;
+pub mod path;
+pub mod batch;
 mod viewing_key;
 mod utils;
 
@@ -0,0 +1,267 @@
+use std::cmp;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{log, Api, Binary, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult, Storage};
+
+use crate::backend::{bucket_load_readonly_file, check_multi_size, file_key, get_namespace_from_path, try_create_file};
+use crate::msg::ManifestResponse;
+use crate::namespace_root::refresh_namespace_entry;
+use crate::nodes::load_node_owner;
+use crate::state::{load, save};
+
+static MANIFEST_LOCATION: &[u8] = b"MANIFESTS";
+static CHUNK_LOCATION: &[u8] = b"MANIFEST_CHUNKS";
+pub static MANIFEST_COUNT_KEY: &[u8] = b"manifest_count";
+
+// Manifests are deleted along with their file, so they can't grow past what one transaction can clear
+pub const MAX_MANIFEST_CHUNKS: u64 = 1000;
+pub const MAX_CHUNK_NODES: usize = 16;
+// Most chunks GetManifest returns per call
+pub const MAX_CHUNK_PAGE: u64 = 100;
+
+// A large file whose bytes live off-chain. The file record itself has no contents; the manifest keyed by
+// its file_key lists the chunks in order. Chunks are keyed by the manifest's id so moving the file only
+// moves this header. `digest` is the uploader's SHA-256 of the whole body, given when finalizing.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct Manifest {
+    id: u64,
+    chunk_count: u64,
+    total_size: u64,
+    finalized: bool,
+    digest: Option<Binary>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ChunkDescriptor {
+    pub hash: Binary,
+    pub size: u64,
+    pub offset: u64,
+    pub nodes: Vec<HumanAddr>,
+}
+
+// A chunk as sent to AppendChunks. Offsets follow from the chunks before it.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct NewChunk {
+    pub hash: Binary,
+    pub size: u64,
+    pub nodes: Vec<HumanAddr>,
+}
+
+fn load_manifest<S: Storage>(store: &S, key: &str) -> StdResult<Option<Manifest>> {
+    bucket_read(MANIFEST_LOCATION, store).may_load(key.as_bytes())
+}
+
+fn save_manifest<S: Storage>(store: &mut S, key: &str, manifest: &Manifest) -> StdResult<()> {
+    bucket(MANIFEST_LOCATION, store).save(key.as_bytes(), manifest)
+}
+
+fn chunk_key(id: u64, index: u64) -> String {
+    format!("{}/{}", id, index)
+}
+
+// Called whenever a file is written or removed, since the new file isn't a manifest until CreateManifest says so
+pub fn clear_manifest<S: Storage>(store: &mut S, key: &str) -> StdResult<()> {
+    let manifest = match load_manifest(store, key)? {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+
+    for index in 0..manifest.chunk_count {
+        bucket::<S, ChunkDescriptor>(CHUNK_LOCATION, store).remove(chunk_key(manifest.id, index).as_bytes());
+    }
+    bucket::<S, Manifest>(MANIFEST_LOCATION, store).remove(key.as_bytes());
+
+    Ok(())
+}
+
+// The digest of an off-chain body, which the contract has no way to compute itself
+pub fn manifest_digest<S: Storage>(store: &S, key: &str) -> Option<Binary> {
+    load_manifest(store, key).unwrap_or(None).and_then(|manifest| manifest.digest)
+}
+
+pub fn move_manifest<S: Storage>(store: &mut S, from_key: &str, to_key: &str) -> StdResult<()> {
+    if let Some(manifest) = load_manifest(store, from_key)? {
+        save_manifest(store, to_key, &manifest)?;
+        bucket::<S, Manifest>(MANIFEST_LOCATION, store).remove(from_key.as_bytes());
+    }
+    Ok(())
+}
+
+// Loads the manifest for `path` once the signer is known to be allowed to change it
+fn writable_manifest<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    path: &str,
+) -> StdResult<(String, Manifest)> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let namespace = get_namespace_from_path(deps, path.to_string()).unwrap_or(String::from("namespace does not exist!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path.to_string(), &namespace)
+        .map_err(|_| StdError::not_found("File"))?;
+    if !file.can_write(signer.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to change this manifest"));
+    }
+
+    let key = file_key(&namespace, path);
+    let manifest = load_manifest(&deps.storage, &key)?.ok_or_else(|| StdError::not_found("Manifest"))?;
+    if manifest.finalized {
+        return Err(StdError::generic_err("This manifest is finalized and can't be changed"));
+    }
+
+    Ok((key, manifest))
+}
+
+// Creates an empty file at `path`, the same way Create does, and starts an empty manifest for it
+pub fn try_create_manifest<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    pkey: String,
+    skey: String,
+) -> StdResult<HandleResponse> {
+    // Fails if there is a folder at `path`
+    try_create_file(deps, env, String::new(), path.clone(), pkey, skey)?;
+
+    let id: u64 = load(&deps.storage, MANIFEST_COUNT_KEY).unwrap_or(0);
+    save(&mut deps.storage, MANIFEST_COUNT_KEY, &(id + 1))?;
+
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let manifest = Manifest { id, chunk_count: 0, total_size: 0, finalized: false, digest: None };
+    save_manifest(&mut deps.storage, &file_key(&namespace, &path), &manifest)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("path", path)],
+        data: None,
+    })
+}
+
+// Adds chunks to the end of the manifest. Every chunk is checked before any is written.
+pub fn try_append_chunks<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    chunks: Vec<NewChunk>,
+) -> StdResult<HandleResponse> {
+    let (key, mut manifest) = writable_manifest(deps, &env, &path)?;
+
+    if chunks.is_empty() {
+        return Err(StdError::generic_err("No chunks to append"));
+    }
+    check_multi_size(&deps.storage, chunks.len())?;
+    if manifest.chunk_count + chunks.len() as u64 > MAX_MANIFEST_CHUNKS {
+        return Err(StdError::generic_err(format!("A manifest can have at most {} chunks", MAX_MANIFEST_CHUNKS)));
+    }
+
+    let mut total_size = manifest.total_size;
+    for chunk in &chunks {
+        if chunk.hash.as_slice().len() != 32 {
+            return Err(StdError::generic_err("Chunk hashes must be 32 bytes"));
+        }
+        if chunk.size == 0 {
+            return Err(StdError::generic_err("Chunks can't be empty"));
+        }
+        if chunk.nodes.len() > MAX_CHUNK_NODES {
+            return Err(StdError::generic_err(format!("A chunk can list at most {} nodes", MAX_CHUNK_NODES)));
+        }
+        if let Some(node) = chunk.nodes.iter().find(|node| load_node_owner(&deps.storage, node.as_str()).is_none()) {
+            return Err(StdError::not_found(format!("Node {}", node)));
+        }
+        total_size = total_size
+            .checked_add(chunk.size)
+            .ok_or_else(|| StdError::generic_err("Manifest size overflow"))?;
+    }
+
+    for chunk in chunks {
+        let descriptor = ChunkDescriptor {
+            hash: chunk.hash,
+            size: chunk.size,
+            offset: manifest.total_size,
+            nodes: chunk.nodes,
+        };
+        bucket(CHUNK_LOCATION, &mut deps.storage).save(chunk_key(manifest.id, manifest.chunk_count).as_bytes(), &descriptor)?;
+        manifest.chunk_count += 1;
+        manifest.total_size += descriptor.size;
+    }
+    save_manifest(&mut deps.storage, &key, &manifest)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("chunk_count", manifest.chunk_count), log("total_size", manifest.total_size)],
+        data: None,
+    })
+}
+
+// Locks the manifest. The expected totals catch an append that never made it on chain.
+pub fn try_finalize_manifest<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    chunk_count: u64,
+    total_size: u64,
+    digest: Option<Binary>,
+) -> StdResult<HandleResponse> {
+    let (key, mut manifest) = writable_manifest(deps, &env, &path)?;
+
+    if digest.as_ref().map_or(false, |digest| digest.as_slice().len() != 32) {
+        return Err(StdError::generic_err("The digest must be a 32 byte SHA-256 hash"));
+    }
+
+    if manifest.chunk_count == 0 {
+        return Err(StdError::generic_err("Append at least one chunk before finalizing"));
+    }
+    if manifest.chunk_count != chunk_count || manifest.total_size != total_size {
+        return Err(StdError::generic_err(format!(
+            "The manifest has {} chunks and {} bytes",
+            manifest.chunk_count, manifest.total_size
+        )));
+    }
+
+    manifest.finalized = true;
+    manifest.digest = digest;
+    save_manifest(&mut deps.storage, &key, &manifest)?;
+
+    // The digest now stands in for the file's contents in the namespace tree
+    let namespace = get_namespace_from_path(deps, path.clone())?;
+    refresh_namespace_entry(&mut deps.storage, &namespace, &path)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("path", path), log("finalized", true)],
+        data: None,
+    })
+}
+
+pub fn query_manifest<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    path: String,
+    behalf: &HumanAddr,
+    start: u64,
+    limit: u64,
+) -> StdResult<ManifestResponse> {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace).map_err(|_| StdError::not_found("File"))?;
+    if !file.can_read(behalf.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to read this manifest"));
+    }
+    let manifest = load_manifest(&deps.storage, &file_key(&namespace, &path))?
+        .ok_or_else(|| StdError::not_found("Manifest"))?;
+
+    let end = cmp::min(manifest.chunk_count, start.saturating_add(cmp::min(limit, MAX_CHUNK_PAGE)));
+    let mut chunks = vec![];
+    for index in start..end {
+        let mut chunk: ChunkDescriptor = bucket_read(CHUNK_LOCATION, &deps.storage).load(chunk_key(manifest.id, index).as_bytes())?;
+        // Nodes are only checked when a chunk is appended, so drop the ones that have left since
+        chunk.nodes.retain(|node| load_node_owner(&deps.storage, node.as_str()).is_some());
+        chunks.push(chunk);
+    }
+
+    Ok(ManifestResponse {
+        chunk_count: manifest.chunk_count,
+        total_size: manifest.total_size,
+        finalized: manifest.finalized,
+        digest: manifest.digest,
+        chunks,
+    })
+}
@@ -0,0 +1,94 @@
+use secret_toolkit::crypto::sha_256;
+
+// Leaves and inner nodes are hashed with different prefixes so an inner node can never pass as a leaf.
+// Clients building a tree for RegisterStorageRoot have to do the same:
+//   leaf = sha256(0x00 || chunk)
+//   node = sha256(0x01 || left || right)
+// When a level has an odd number of nodes the last one moves up to the next level unchanged.
+
+pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
+    let mut bytes = Vec::with_capacity(data.len() + 1);
+    bytes.push(0u8);
+    bytes.extend_from_slice(data);
+    sha_256(&bytes)
+}
+
+pub fn hash_node(left: &[u8], right: &[u8]) -> [u8; 32] {
+    let mut bytes = Vec::with_capacity(left.len() + right.len() + 1);
+    bytes.push(1u8);
+    bytes.extend_from_slice(left);
+    bytes.extend_from_slice(right);
+    sha_256(&bytes)
+}
+
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+// Sibling hashes from the leaf up to the root, skipping levels where the node is promoted without a sibling
+pub fn merkle_proof(leaves: &[[u8; 32]], index: usize) -> Vec<[u8; 32]> {
+    let mut proof = vec![];
+    let mut level = leaves.to_vec();
+    let mut index = index;
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push(level[sibling]);
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash_node(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        index /= 2;
+    }
+    proof
+}
+
+pub fn verify_proof(root: &[u8], leaf: &[u8; 32], index: u64, leaf_count: u64, proof: &[Vec<u8>]) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+
+    let mut hash = *leaf;
+    let mut index = index;
+    let mut width = leaf_count;
+    let mut proof = proof.iter();
+
+    while width > 1 {
+        if index % 2 == 1 {
+            match proof.next() {
+                Some(sibling) => hash = hash_node(sibling, &hash),
+                None => return false,
+            }
+        } else if index + 1 < width {
+            match proof.next() {
+                Some(sibling) => hash = hash_node(&hash, sibling),
+                None => return false,
+            }
+        }
+        index /= 2;
+        width = (width + 1) / 2;
+    }
+
+    proof.next().is_none() && hash[..] == root[..]
+}
@@ -1,8 +1,17 @@
-use cosmwasm_std::HumanAddr;
+use cosmwasm_std::{Binary, Coin, HumanAddr, StdResult, Uint128};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{backend::File, viewing_key::ViewingKey};
+use crate::batch::{FileOp, FileOpResult};
+use crate::challenge::Challenge;
+use crate::endpoint::NodeEndpoint;
+use crate::manifest::{ChunkDescriptor, NewChunk};
+use crate::nodes::NodeProfile;
+use crate::path::{canonical_path, canonical_paths};
+use crate::reputation::ScoreEvent;
+use crate::rewards::EpochPayout;
+use crate::state::{Config, ContractStatus, RewardSource};
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct InitMsg {
@@ -14,13 +23,16 @@ pub struct InitMsg {
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleMsg {
+    // `contents` is ignored now that folders have none, but kept so existing clients still work
     InitAddress { contents: String, entropy: String },
     Create {contents: String, path: String , pkey: String, skey: String},
+    CreateFolder {path: String},
     CreateMulti { contents_list: Vec<String>, path_list: Vec<String>, pkey_list: Vec<String>, skey_list: Vec<String> },
     Remove {path: String},
     RemoveMulti {path_list: Vec<String>},
     Move {old_path: String, new_path: String},
     MoveMulti {old_path_list: Vec<String>, new_path_list: Vec<String>},
+    Batch {ops: Vec<FileOp>},
     CreateViewingKey {entropy: String, padding: Option<String>},
     AllowRead {path: String, address_list: Vec<String>},
     DisallowRead {path: String, address_list: Vec<String>},
@@ -29,21 +41,102 @@ pub enum HandleMsg {
     DisallowWrite {path: String, address_list: Vec<String>},
     ResetWrite {path: String},
     InitNode {ip: String, address: String},
+    UpdateNode {new_ip: String},
+    UpdateNodeInfo {profile: NodeProfile},
+    RemoveNode {},
+    WithdrawStake {},
+    NodeHeartbeat {},
+    ConfirmStorage {file: String},
+    ReportNodeFailure {ip: String, path: String},
+    DecayScores {start: u64, limit: u64},
+    RegisterStorageRoot {path: String, merkle_root: Binary, chunk_count: u64},
+    RequestChallenge {},
+    AnswerChallenge {chunk: Binary, proof: Vec<Binary>, response: Binary},
+    ExpireChallenge {ip: String},
     ClaimReward {path: String, key: String, address: String},
+    BindClaimCode {path: String, node: HumanAddr},
+    WithdrawRewards {amount: u32},
+    SettleEscrow {path: String},
+    WithdrawEscrow {},
+    DistributeEpoch {limit: u64},
+    CreateManifest {path: String, pkey: String, skey: String},
+    AppendChunks {path: String, chunks: Vec<NewChunk>},
+    FinalizeManifest {path: String, chunk_count: u64, total_size: u64, digest: Option<Binary>},
+    BeginUpload {path: String, total_size: u64, chunk_count: u64},
+    UploadChunk {session: u64, index: u64, data: String},
+    CommitUpload {session: u64, pkey: String, skey: String},
+    AbortUpload {session: u64},
     ForgetMe { },
-    ChangeOwner {path: String, new_owner: String}
+    SweepForgotten {address: HumanAddr, limit: u64},
+    RecordNamespaceRoot {},
+    ChangeOwner {path: String, new_owner: String},
+    ChangeAdmin {address: HumanAddr},
+    AcceptAdmin {},
+    UpdateConfig {config: ConfigUpdate},
+    SetContractStatus {level: ContractStatus},
+    SetPause {file_writes: Option<bool>, permission_changes: Option<bool>, node_registration: Option<bool>, reward_claims: Option<bool>},
+    AddNodeAllowlist {addresses: Vec<HumanAddr>},
+    RemoveNodeAllowlist {addresses: Vec<HumanAddr>},
+    FundRewardPool {},
+    SlashNode {address: HumanAddr, percent: u64},
+    WithdrawSlashedStake {recipient: HumanAddr},
+}
+
+// Fields left as None keep their current value
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+pub struct ConfigUpdate {
+    pub max_contents_size: Option<u64>,
+    pub max_multi_size: Option<u64>,
+    pub max_node_list_size: Option<u64>,
+    pub node_allowlist_enabled: Option<bool>,
+    pub heartbeat_interval: Option<u64>,
+    pub score_decay_blocks: Option<u64>,
+    pub challenge_window: Option<u64>,
+    pub reward_source: Option<RewardSource>,
+    pub reward_per_credit: Option<Uint128>,
+    pub min_node_stake: Option<Uint128>,
+    pub stake_denom: Option<String>,
+    pub unbonding_period: Option<u64>,
+    pub slash_percent: Option<u64>,
+    pub replication_factor: Option<u64>,
+    pub min_select_score: Option<u32>,
+    pub escrow_epochs: Option<u64>,
+    pub payment_denom: Option<String>,
+    pub write_fee: Option<Uint128>,
+    pub epoch_emission: Option<u32>,
+    pub upload_window: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     GetContents { behalf: HumanAddr, path: String, key: String },
+    GetManifest { behalf: HumanAddr, path: String, key: String, start: u64, limit: u64 },
+    VerifyContent { behalf: HumanAddr, path: String, key: String, digest: Binary },
+    GetNamespaceProof { behalf: HumanAddr, path: String, key: String },
+    GetPlacement { behalf: HumanAddr, path: String, key: String },
+    GetUploadSession { behalf: HumanAddr, session: u64, key: String },
     GetNodeIP {index: u64},
     GetNodeListSize {},
-    GetNodeList{size: u64},
+    GetNodeList{start_after: Option<String>, limit: Option<u64>, min_score: Option<u32>},
+    GetNode {ip: String},
     GetNodeCoins{address: String},
     YouUpBro{address: String},
     GetWalletInfo { behalf: HumanAddr, key: String},
+    GetConfig {},
+    GetPauseMatrix {},
+    GetScoreHistory {ip: String},
+    GetChallenge {ip: String},
+    GetRewardPool {},
+    GetStake {address: HumanAddr},
+    GetUnderReplicated {start: u64, limit: u64},
+    SelectNodes {count: u64, file_hint: String},
+    FindNodes {start_after: Option<String>, size: u64, region: Option<String>, min_free_bytes: Option<u64>},
+    GetEscrow {path: String},
+    GetEscrowBalance {address: HumanAddr},
+    GetEpochPayouts {address: HumanAddr},
+    GetNamespaceRoot {address: HumanAddr},
+    GetNamespaceSnapshot {address: HumanAddr, height: u64},
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
@@ -51,6 +144,9 @@ pub enum QueryMsg {
 pub enum HandleAnswer {
     DefaultAnswer { status:ResponseStatus},
     CreateViewingKey { key: ViewingKey },
+    Challenge { challenge: Challenge },
+    UploadSession { session: u64 },
+    Batch { results: Vec<FileOpResult> },
 }
 
 // We define a custom struct for each query response
@@ -61,9 +157,72 @@ pub struct WalletInfoResponse {
     pub counter: i32
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub admin: HumanAddr,
+    pub pending_admin: Option<HumanAddr>,
+    pub status: ContractStatus,
+    pub config: Config,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NodeInfo {
+    pub ip: String,
+    pub endpoint: Option<NodeEndpoint>,
+    pub address: String,
+    pub score: u32,
+    pub profile: NodeProfile,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NodeListResponse {
+    pub nodes: Vec<NodeInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FindNodesResponse {
+    pub nodes: Vec<NodeInfo>,
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ScoreHistoryResponse {
+    pub score: u32,
+    pub last_heartbeat: u64,
+    pub history: Vec<ScoreEvent>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardPoolResponse {
+    pub source: RewardSource,
+    pub balance: Uint128,
+    pub reward_per_credit: Uint128,
+    pub slashed_stake: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PlacementInfo {
+    pub file: String,
+    pub root: Option<String>,
+    pub nodes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnderReplicatedResponse {
+    pub files: Vec<PlacementInfo>,
+    pub next: Option<u64>,
+}
+
+// `digest` is the SHA-256 of the contents, or for a manifest the digest its uploader gave
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct FileResponse {
     pub file: File,
+    pub digest: Binary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VerifyContentResponse {
+    pub matches: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -79,11 +238,68 @@ pub struct BigTreeResponse {
     pub files: Vec<String>,
 }
 
+impl HandleMsg {
+    // Every file path is put in canonical form here, before any handler touches storage
+    pub fn canonicalize_paths(self) -> StdResult<Self> {
+        Ok(match self {
+            Self::Create { contents, path, pkey, skey } => Self::Create { contents, path: canonical_path(&path)?, pkey, skey },
+            Self::CreateFolder { path } => Self::CreateFolder { path: canonical_path(&path)? },
+            Self::CreateMulti { contents_list, path_list, pkey_list, skey_list } => {
+                Self::CreateMulti { contents_list, path_list: canonical_paths(path_list)?, pkey_list, skey_list }
+            }
+            Self::Remove { path } => Self::Remove { path: canonical_path(&path)? },
+            Self::RemoveMulti { path_list } => Self::RemoveMulti { path_list: canonical_paths(path_list)? },
+            Self::Move { old_path, new_path } => Self::Move { old_path: canonical_path(&old_path)?, new_path: canonical_path(&new_path)? },
+            Self::MoveMulti { old_path_list, new_path_list } => {
+                Self::MoveMulti { old_path_list: canonical_paths(old_path_list)?, new_path_list: canonical_paths(new_path_list)? }
+            }
+            Self::Batch { ops } => Self::Batch { ops: ops.into_iter().map(FileOp::canonicalize_paths).collect::<StdResult<_>>()? },
+            Self::AllowRead { path, address_list } => Self::AllowRead { path: canonical_path(&path)?, address_list },
+            Self::DisallowRead { path, address_list } => Self::DisallowRead { path: canonical_path(&path)?, address_list },
+            Self::ResetRead { path } => Self::ResetRead { path: canonical_path(&path)? },
+            Self::AllowWrite { path, address_list } => Self::AllowWrite { path: canonical_path(&path)?, address_list },
+            Self::DisallowWrite { path, address_list } => Self::DisallowWrite { path: canonical_path(&path)?, address_list },
+            Self::ResetWrite { path } => Self::ResetWrite { path: canonical_path(&path)? },
+            Self::ReportNodeFailure { ip, path } => Self::ReportNodeFailure { ip, path: canonical_path(&path)? },
+            Self::RegisterStorageRoot { path, merkle_root, chunk_count } => {
+                Self::RegisterStorageRoot { path: canonical_path(&path)?, merkle_root, chunk_count }
+            }
+            Self::SettleEscrow { path } => Self::SettleEscrow { path: canonical_path(&path)? },
+            Self::CreateManifest { path, pkey, skey } => Self::CreateManifest { path: canonical_path(&path)?, pkey, skey },
+            Self::AppendChunks { path, chunks } => Self::AppendChunks { path: canonical_path(&path)?, chunks },
+            Self::FinalizeManifest { path, chunk_count, total_size, digest } => {
+                Self::FinalizeManifest { path: canonical_path(&path)?, chunk_count, total_size, digest }
+            }
+            Self::BeginUpload { path, total_size, chunk_count } => Self::BeginUpload { path: canonical_path(&path)?, total_size, chunk_count },
+            Self::ChangeOwner { path, new_owner } => Self::ChangeOwner { path: canonical_path(&path)?, new_owner },
+            // ClaimReward's and BindClaimCode's `path` is the claim code's public key, not a file path
+            msg => msg,
+        })
+    }
+}
+
 impl QueryMsg {
+    pub fn canonicalize_paths(self) -> StdResult<Self> {
+        Ok(match self {
+            Self::GetContents { behalf, path, key } => Self::GetContents { behalf, path: canonical_path(&path)?, key },
+            Self::GetManifest { behalf, path, key, start, limit } => Self::GetManifest { behalf, path: canonical_path(&path)?, key, start, limit },
+            Self::VerifyContent { behalf, path, key, digest } => Self::VerifyContent { behalf, path: canonical_path(&path)?, key, digest },
+            Self::GetNamespaceProof { behalf, path, key } => Self::GetNamespaceProof { behalf, path: canonical_path(&path)?, key },
+            Self::GetPlacement { behalf, path, key } => Self::GetPlacement { behalf, path: canonical_path(&path)?, key },
+            Self::GetEscrow { path } => Self::GetEscrow { path: canonical_path(&path)? },
+            msg => msg,
+        })
+    }
+
     pub fn get_validation_params(&self) -> (Vec<&HumanAddr>, ViewingKey) {
         match self {
             Self::GetContents { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
             Self::GetWalletInfo { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
+            Self::GetManifest { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
+            Self::VerifyContent { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
+            Self::GetNamespaceProof { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
+            Self::GetPlacement { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
+            Self::GetUploadSession { behalf, key, .. } => (vec![behalf], ViewingKey(key.clone())),
             _ => panic!("This query type does not require authentication"),
         }
     }
@@ -94,4 +310,31 @@ impl QueryMsg {
 pub enum ResponseStatus {
     Success,
     Failure,
-}
\ No newline at end of file
+}
+
+// Latest payouts first to last. `next_epoch` is the epoch DistributeEpoch will pay out next.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EpochPayoutsResponse {
+    pub next_epoch: Option<u64>,
+    pub payouts: Vec<EpochPayout>,
+}
+
+// `chunks` is the requested page; the totals cover the whole manifest
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ManifestResponse {
+    pub chunk_count: u64,
+    pub total_size: u64,
+    pub finalized: bool,
+    pub digest: Option<Binary>,
+    pub chunks: Vec<ChunkDescriptor>,
+}
+
+// `proof` holds the sibling hashes from the leaf up; see namespace_root::verify_namespace_proof
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NamespaceProofResponse {
+    pub root: Binary,
+    pub leaf_count: u64,
+    pub index: u64,
+    pub digest: Binary,
+    pub proof: Vec<Binary>,
+}
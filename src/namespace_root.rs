@@ -0,0 +1,271 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use secret_toolkit::crypto::sha_256;
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{log, Api, Binary, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult, Storage};
+
+use crate::backend::{bucket_load_readonly_file, file_digest, file_key, get_namespace_from_path};
+use crate::merkle::{hash_leaf, hash_node, verify_proof};
+use crate::msg::NamespaceProofResponse;
+
+static TREE_LOCATION: &[u8] = b"NAMESPACE_TREES";
+static NODE_LOCATION: &[u8] = b"NAMESPACE_TREE_NODES";
+static INDEX_LOCATION: &[u8] = b"NAMESPACE_TREE_INDEX";
+static LEAF_PATH_LOCATION: &[u8] = b"NAMESPACE_TREE_PATHS";
+static SNAPSHOT_LOCATION: &[u8] = b"NAMESPACE_SNAPSHOTS";
+static SNAPSHOT_COUNT_LOCATION: &[u8] = b"NAMESPACE_SNAPSHOT_COUNT";
+
+// Every file and folder in a namespace is a leaf of that namespace's tree, hashed from its path and its
+// file_digest: the SHA-256 of its on-chain contents, or the digest its manifest was finalized with. The tree is built the same way as the trees in
+// merkle.rs. Leaves are kept in a list with swap-remove, and each write only rehashes the nodes above the leaves
+// it touched, so a write costs O(log n) however large the namespace is.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct NamespaceTree {
+    pub root: Binary,
+    pub leaf_count: u64,
+}
+
+// A root the owner recorded with RecordNamespaceRoot. Queries can't see the block height, so this is how a
+// root gets tied to one: a proof taken at `height` can be checked against it long after the tree has changed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct NamespaceSnapshot {
+    pub height: u64,
+    pub root: Binary,
+    pub leaf_count: u64,
+}
+
+// Fixed width, so no (path, digest) pair can be confused with another
+pub fn namespace_leaf(path: &str, digest: &[u8]) -> [u8; 32] {
+    let mut bytes = sha_256(path.as_bytes()).to_vec();
+    bytes.extend_from_slice(digest);
+    hash_leaf(&bytes)
+}
+
+// Checks a proof from GetNamespaceProof against a root from GetNamespaceRoot, without touching the chain
+pub fn verify_namespace_proof(
+    root: &[u8],
+    path: &str,
+    digest: &[u8],
+    index: u64,
+    leaf_count: u64,
+    proof: &[Binary],
+) -> bool {
+    let proof: Vec<Vec<u8>> = proof.iter().map(|sibling| sibling.as_slice().to_vec()).collect();
+    verify_proof(root, &namespace_leaf(path, digest), index, leaf_count, &proof)
+}
+
+fn load_tree<S: Storage>(store: &S, namespace: &str) -> NamespaceTree {
+    bucket_read(TREE_LOCATION, store)
+        .may_load(namespace.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(NamespaceTree { root: Binary(vec![]), leaf_count: 0 })
+}
+
+fn node_key(namespace: &str, level: u32, index: u64) -> String {
+    format!("{}/{}/{}", namespace, level, index)
+}
+
+fn load_node<S: Storage>(store: &S, namespace: &str, level: u32, index: u64) -> StdResult<Binary> {
+    bucket_read(NODE_LOCATION, store).load(node_key(namespace, level, index).as_bytes())
+}
+
+fn save_node<S: Storage>(store: &mut S, namespace: &str, level: u32, index: u64, hash: &[u8]) -> StdResult<()> {
+    bucket(NODE_LOCATION, store).save(node_key(namespace, level, index).as_bytes(), &Binary(hash.to_vec()))
+}
+
+fn load_index<S: Storage>(store: &S, namespace: &str, path: &str) -> StdResult<Option<u64>> {
+    bucket_read(INDEX_LOCATION, store).may_load(file_key(namespace, path).as_bytes())
+}
+
+fn place_leaf<S: Storage>(store: &mut S, namespace: &str, index: u64, path: &str, leaf: &[u8]) -> StdResult<()> {
+    save_node(store, namespace, 0, index, leaf)?;
+    bucket(INDEX_LOCATION, store).save(file_key(namespace, path).as_bytes(), &index)?;
+    bucket(LEAF_PATH_LOCATION, store).save(node_key(namespace, 0, index).as_bytes(), &path.to_string())
+}
+
+// Rehashes the nodes above leaf `index` and returns the root. A node without a sibling moves up unchanged.
+fn rehash<S: Storage>(store: &mut S, namespace: &str, index: u64, leaf_count: u64) -> StdResult<Binary> {
+    let mut hash = load_node(store, namespace, 0, index)?;
+    let mut index = index;
+    let mut width = leaf_count;
+    let mut level = 0;
+
+    while width > 1 {
+        if index % 2 == 1 {
+            let left = load_node(store, namespace, level, index - 1)?;
+            hash = Binary(hash_node(left.as_slice(), hash.as_slice()).to_vec());
+        } else if index + 1 < width {
+            let right = load_node(store, namespace, level, index + 1)?;
+            hash = Binary(hash_node(hash.as_slice(), right.as_slice()).to_vec());
+        }
+        index /= 2;
+        width = (width + 1) / 2;
+        level += 1;
+        save_node(store, namespace, level, index, hash.as_slice())?;
+    }
+
+    Ok(hash)
+}
+
+// Adds `path` to the tree, or updates its leaf if it is already there
+pub fn set_namespace_entry<S: Storage>(store: &mut S, namespace: &str, path: &str, digest: &[u8]) -> StdResult<()> {
+    let mut tree = load_tree(store, namespace);
+    let index = match load_index(store, namespace, path)? {
+        Some(index) => index,
+        None => {
+            tree.leaf_count += 1;
+            tree.leaf_count - 1
+        }
+    };
+
+    place_leaf(store, namespace, index, path, &namespace_leaf(path, digest))?;
+    tree.root = rehash(store, namespace, index, tree.leaf_count)?;
+    bucket(TREE_LOCATION, store).save(namespace.as_bytes(), &tree)
+}
+
+// Rewrites the leaf for `path` from file_digest, for when what the digest comes from changes without the
+// stored contents changing, like a manifest being finalized
+pub fn refresh_namespace_entry<S: Storage>(store: &mut S, namespace: &str, path: &str) -> StdResult<()> {
+    let digest = file_digest(store, &path.to_string(), &namespace.to_string())?;
+    set_namespace_entry(store, namespace, path, digest.as_slice())
+}
+
+// The last leaf takes the removed one's place
+pub fn remove_namespace_entry<S: Storage>(store: &mut S, namespace: &str, path: &str) -> StdResult<()> {
+    let index = match load_index(store, namespace, path)? {
+        Some(index) => index,
+        None => return Ok(()),
+    };
+    let mut tree = load_tree(store, namespace);
+    let last = tree.leaf_count - 1;
+
+    if index != last {
+        let moved: String = bucket_read(LEAF_PATH_LOCATION, store).load(node_key(namespace, 0, last).as_bytes())?;
+        let leaf = load_node(store, namespace, 0, last)?;
+        place_leaf(store, namespace, index, &moved, leaf.as_slice())?;
+    }
+    bucket::<S, u64>(INDEX_LOCATION, store).remove(file_key(namespace, path).as_bytes());
+    bucket::<S, String>(LEAF_PATH_LOCATION, store).remove(node_key(namespace, 0, last).as_bytes());
+    bucket::<S, Binary>(NODE_LOCATION, store).remove(node_key(namespace, 0, last).as_bytes());
+    tree.leaf_count = last;
+
+    // The right edge is rehashed last, since the moved leaf's path may run through it
+    if tree.leaf_count == 0 {
+        tree.root = Binary(vec![]);
+    } else {
+        if index < tree.leaf_count {
+            rehash(store, namespace, index, tree.leaf_count)?;
+        }
+        tree.root = rehash(store, namespace, tree.leaf_count - 1, tree.leaf_count)?;
+    }
+    bucket(TREE_LOCATION, store).save(namespace.as_bytes(), &tree)
+}
+
+pub fn namespace_leaf_count<S: Storage>(store: &S, namespace: &str) -> u64 {
+    load_tree(store, namespace).leaf_count
+}
+
+pub fn namespace_path_at<S: Storage>(store: &S, namespace: &str, index: u64) -> StdResult<String> {
+    bucket_read(LEAF_PATH_LOCATION, store).load(node_key(namespace, 0, index).as_bytes())
+}
+
+// `root` is empty while the namespace has no entries
+pub fn query_namespace_root<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<NamespaceTree> {
+    let namespace = get_namespace_from_path(deps, address.to_string())?;
+    Ok(load_tree(&deps.storage, &namespace))
+}
+
+// Sibling hashes from the leaf up, as verify_namespace_proof expects them. Only a reader of the file can ask,
+// since the proof includes the digest of its contents.
+pub fn query_namespace_proof<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    path: String,
+    behalf: &HumanAddr,
+) -> StdResult<NamespaceProofResponse> {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace not found!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace).map_err(|_| StdError::not_found("File"))?;
+    if !file.can_read(behalf.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to read this file"));
+    }
+
+    let tree = load_tree(&deps.storage, &namespace);
+    let index = load_index(&deps.storage, &namespace, &path)?.ok_or_else(|| StdError::not_found("Namespace entry"))?;
+
+    let mut proof = vec![];
+    let mut position = index;
+    let mut width = tree.leaf_count;
+    let mut level = 0;
+    while width > 1 {
+        let sibling = position ^ 1;
+        if sibling < width {
+            proof.push(load_node(&deps.storage, &namespace, level, sibling)?);
+        }
+        position /= 2;
+        width = (width + 1) / 2;
+        level += 1;
+    }
+
+    Ok(NamespaceProofResponse {
+        root: tree.root,
+        leaf_count: tree.leaf_count,
+        index,
+        digest: file_digest(&deps.storage, &path, &namespace)?,
+        proof,
+    })
+}
+
+fn snapshot_count<S: Storage>(store: &S, namespace: &str) -> u64 {
+    bucket_read(SNAPSHOT_COUNT_LOCATION, store).may_load(namespace.as_bytes()).unwrap_or(None).unwrap_or(0)
+}
+
+fn load_snapshot<S: Storage>(store: &S, namespace: &str, index: u64) -> StdResult<NamespaceSnapshot> {
+    bucket_read(SNAPSHOT_LOCATION, store).load(format!("{}/{}", namespace, index).as_bytes())
+}
+
+// Records the sender's current root at this height. Recording twice in one block keeps the later root.
+pub fn try_record_namespace_root<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let namespace = get_namespace_from_path(deps, env.message.sender.to_string())?;
+    let tree = load_tree(&deps.storage, &namespace);
+
+    let mut count = snapshot_count(&deps.storage, &namespace);
+    if count > 0 && load_snapshot(&deps.storage, &namespace, count - 1)?.height == env.block.height {
+        count -= 1;
+    }
+    let snapshot = NamespaceSnapshot { height: env.block.height, root: tree.root, leaf_count: tree.leaf_count };
+    bucket(SNAPSHOT_LOCATION, &mut deps.storage).save(format!("{}/{}", namespace, count).as_bytes(), &snapshot)?;
+    bucket(SNAPSHOT_COUNT_LOCATION, &mut deps.storage).save(namespace.as_bytes(), &(count + 1))?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("height", env.block.height), log("root", snapshot.root.to_base64())],
+        data: None,
+    })
+}
+
+// The latest snapshot recorded at or before `height`. Snapshots are stored in height order, so this is a binary search.
+pub fn query_namespace_snapshot<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+    height: u64,
+) -> StdResult<NamespaceSnapshot> {
+    let namespace = get_namespace_from_path(deps, address.to_string())?;
+    let (mut low, mut high) = (0, snapshot_count(&deps.storage, &namespace));
+    while low < high {
+        let middle = (low + high) / 2;
+        if load_snapshot(&deps.storage, &namespace, middle)?.height <= height {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    if low == 0 {
+        return Err(StdError::not_found("Namespace root snapshot"));
+    }
+    load_snapshot(&deps.storage, &namespace, low - 1)
+}
@@ -1,14 +1,31 @@
 // use std::str::from_utf8;
+use std::cmp;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use cosmwasm_storage::{ bucket, bucket_read};
-use cosmwasm_std::{to_binary, Api, Querier, Storage, StdResult, StdError, Extern, HandleResponse};
+use cosmwasm_std::{log, to_binary, Api, Env, HumanAddr, Querier, Storage, StdResult, StdError, Extern, HandleResponse, Uint128};
+use secret_toolkit::crypto::sha_256;
 
+use crate::admin::check_admin;
+use crate::endpoint::{endpoint_host, normalize_endpoint, NodeEndpoint};
+use crate::msg::NodeInfo;
+use crate::reputation::{refresh_score, stop_tracking};
+use crate::staking::start_unbonding;
+use crate::state::{load, State, CONFIG_KEY};
+use crate::utils::ct_slice_compare;
 
 static NODE_LOCATION: &[u8] = b"NODES";
 static NODE_LOC_LOCATION: &[u8] = b"NODE_LOC";
 static NODE_MAP_DATA: &[u8] = b"NODE_MAP";
+static NODE_OWNER_LOCATION: &[u8] = b"NODE_OWNER";
+static NODE_INDEX_LOCATION: &[u8] = b"NODE_INDEX";
+static NODE_ALLOWLIST: &[u8] = b"NODE_ALLOWLIST";
+// Second list over the same nodes, kept sorted by score from highest to lowest
+static NODE_RANK_LOCATION: &[u8] = b"NODE_RANK";
+static NODE_RANK_POS_LOCATION: &[u8] = b"NODE_RANK_POS";
+// Host of each node's endpoint to the node's ip
+static NODE_HOST_LOCATION: &[u8] = b"NODE_HOSTS";
 
 static NODE_CLAIM_CODES: &[u8] = b"CLAIM_CODES";
 
@@ -39,57 +56,120 @@ fn query_coins<'a, S: Storage>(store: &'a S, address: String) -> u32{
     }
 }
 
+pub fn add_coins<'a, S: Storage>(store: &'a mut S, address: &str, amount: u32) -> StdResult<u32> {
+    let count = query_coins(store, address.to_string())
+        .checked_add(amount)
+        .ok_or_else(|| StdError::generic_err(format!("An address can't hold more than {} reward credits", u32::MAX)))?;
+    bucket(COIN_COUNT, store).save(address.as_bytes(), &count)?;
+    Ok(count)
+}
+
+pub fn burn_coins<'a, S: Storage>(store: &'a mut S, address: &str, amount: u32) -> StdResult<u32> {
+    let count = query_coins(store, address.to_string());
+    if amount > count {
+        return Err(StdError::generic_err(format!("Only {} reward credits available", count)));
+    }
+    bucket(COIN_COUNT, store).save(address.as_bytes(), &(count - amount))?;
+    Ok(count - amount)
+}
+
+// Codes are only kept as a hash. The uploader binds each code to the node it hands the code to, so no other
+// node can redeem it. Once redeemed it stays on record as claimed, so a replay gets a clear error instead of
+// another credit.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ClaimCode {
+    code_hash: Vec<u8>,
+    #[serde(default)]
+    node: Option<String>,
+    claimed_by: Option<String>,
+}
+
+fn claim_key(uploader: &str, claim_path: &str) -> String {
+    format!("{}/{}", uploader, claim_path)
+}
+
+// `address` is the uploader who wrote the claim code. The credit goes to the node signing the claim.
 pub fn claim<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
+    env: Env,
     claim_path: String, 
     claim_code: String, 
     address: String
 )-> StdResult<HandleResponse> {
-
-    
-    let mut acl = String::from(&address);
-    acl.push_str(&claim_path);
-
-
-    let resp:Result<String, StdError> = bucket_read(NODE_CLAIM_CODES, &deps.storage).load(acl.as_bytes());
-    let r = resp.unwrap();
-
-    let count_resp:Result<u32, StdError> = bucket_read(COIN_COUNT, &deps.storage).load(address.as_bytes());
-
-    let mut old_count:u32 = 0;
-    match count_resp {
-        Ok(count) => {
-
-            old_count = count;
-        },
-        Err(_e) => {}
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    if load_node_owner(&deps.storage, signer.as_str()).is_none() {
+        return Err(StdError::generic_err("Only registered nodes can claim rewards"));
     }
 
-    old_count += 1;
+    let key = claim_key(&address, &claim_path);
+    let record: Option<ClaimCode> = bucket_read(NODE_CLAIM_CODES, &deps.storage).may_load(key.as_bytes())?;
+    let mut record = record.ok_or_else(|| StdError::not_found("Claim code"))?;
 
-    if claim_code.eq(&r)  {
+    if let Some(node) = &record.claimed_by {
+        return Err(StdError::generic_err(format!("This claim code was already redeemed by {}", node)));
+    }
+    if record.node.as_deref() != Some(signer.as_str()) {
+        return Err(StdError::generic_err("This claim code isn't bound to this node"));
+    }
 
-        let _bucket_response = bucket(COIN_COUNT, &mut deps.storage).save(address.as_bytes(), &old_count);
+    if !ct_slice_compare(&sha_256(claim_code.as_bytes()), &record.code_hash) {
+        return Err(StdError::generic_err("Invalid claim code"));
+    }
 
-        bucket::<S, String>(NODE_CLAIM_CODES, &mut deps.storage).remove(claim_path.as_bytes());
+    record.claimed_by = Some(signer.to_string());
+    bucket(NODE_CLAIM_CODES, &mut deps.storage).save(key.as_bytes(), &record)?;
 
-        
-    }
+    let count = add_coins(&mut deps.storage, signer.as_str(), 1)?;
 
     Ok(HandleResponse {
         messages: vec![],
-        log: vec![],
+        log: vec![
+            log("claim", claim_path),
+            log("node", signer),
+            log("coins", count),
+        ],
         data: Some(to_binary("OK")?),
     })
 
 }
 
-pub fn write_claim<'a, S: Storage>(store: &'a mut S, claim_path: String, claim_code: String) {
+// Sent by the uploader. Binding an unredeemed code again moves it to the new node.
+pub fn try_bind_claim_code<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    claim_path: String,
+    node: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    if load_node_owner(&deps.storage, node.as_str()).is_none() {
+        return Err(StdError::generic_err("Claim codes can only be bound to registered nodes"));
+    }
 
-    let c = &claim_path;
+    let key = claim_key(signer.as_str(), &claim_path);
+    let record: Option<ClaimCode> = bucket_read(NODE_CLAIM_CODES, &deps.storage).may_load(key.as_bytes())?;
+    let mut record = record.ok_or_else(|| StdError::not_found("Claim code"))?;
+    if record.claimed_by.is_some() {
+        return Err(StdError::generic_err("This claim code was already redeemed"));
+    }
+
+    record.node = Some(node.to_string());
+    bucket(NODE_CLAIM_CODES, &mut deps.storage).save(key.as_bytes(), &record)?;
 
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("claim", claim_path), log("node", node)],
+        data: None,
+    })
+}
+
+pub fn write_claim<'a, S: Storage>(store: &'a mut S, uploader: &str, claim_path: &str, claim_code: &str) {
+    let record = ClaimCode {
+        code_hash: sha_256(claim_code.as_bytes()).to_vec(),
+        node: None,
+        claimed_by: None,
+    };
 
-    let bucket_response = bucket(NODE_CLAIM_CODES, store).save(c.as_bytes(), &claim_code);
+    let bucket_response = bucket(NODE_CLAIM_CODES, store).save(claim_key(uploader, claim_path).as_bytes(), &record);
     match bucket_response {
         Ok(bucket_response) => bucket_response,
         Err(e) => panic!("Bucket Error: {}", e)
@@ -102,40 +182,255 @@ pub fn write_claim<'a, S: Storage>(store: &'a mut S, claim_path: String, claim_c
 
 
 
+pub const STARTING_SCORE: u32 = 500;
+// Hard cap on nodes returned by one query, whatever max_node_list_size is set to
+pub const MAX_NODE_PAGE: u64 = 100;
+// Most ranks one FindNodes walks, however few of them match
+pub const MAX_FIND_SCAN: u64 = 300;
+
+// Upper bounds on what a node can advertise, to keep node entries small
+pub const MAX_REGION_LEN: usize = 32;
+pub const MAX_PROTOCOLS: usize = 8;
+pub const MAX_PROTOCOL_LEN: usize = 32;
+
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct NodeData {
     score: u32,
     secret_address: String,
+    // Nodes registered before profiles existed load with an empty one
+    #[serde(default)]
+    profile: NodeProfile,
 } 
 
-pub fn get_node<'a, S: Storage>(store: &'a S, index: u64) -> String {
+// What a node advertises to clients choosing where to store data. Set with UpdateNodeInfo.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct NodeProfile {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub region: String,
+    pub protocols: Vec<String>,
+    pub price_per_gb_month: Uint128,
+}
+
+impl NodeData {
+    pub fn get_score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn get_secret_address(&self) -> &str {
+        &self.secret_address
+    }
+
+    pub fn get_profile(&self) -> &NodeProfile {
+        &self.profile
+    }
+}
+
+pub fn get_node<'a, S: Storage>(store: &'a S, index: u64) -> Option<String> {
     let size = get_node_size(store);
 
     if index >= size {
-        return String::from("null");
+        return None;
     }
 
-    load_node_loc(store, index.to_string())
+    Some(load_node_loc(store, index.to_string()))
 }
 
-pub fn push_node<'a, S: Storage>(store: &'a mut S, ip: String, address: String) {
+pub fn push_node<'a, S: Storage>(store: &'a mut S, ip: String, address: String, score: u32) {
 
     let size = get_node_size(store);
 
 
     save_node_loc(store, size.to_string(), ip.clone());
+    save_node_index(store, ip.clone(), size);
 
     let node = NodeData {
-        score: 500,
-        secret_address: address
+        score,
+        secret_address: address,
+        profile: NodeProfile::default(),
     };
 
-    save_node_data(store, ip, node);
+    save_node_data(store, ip.clone(), node.clone());
+    save_node_owner(store, node.secret_address, ip.clone());
+    save_node_host(store, &ip);
+    save_rank(store, size, &ip);
 
     let size = size + 1;
 
     set_node_size(store, size);
 
+    resort_rank(store, size - 1, score);
+}
+
+pub fn set_node_score<'a, S: Storage>(store: &'a mut S, ip: String, score: u32) {
+    let mut node = load_node_data(store, ip.clone());
+    node.score = score;
+    save_node_data(store, ip.clone(), node);
+
+    let rank = load_rank_pos(store, &ip);
+    resort_rank(store, rank, score);
+}
+
+pub fn node_info(ip: String, node: NodeData) -> NodeInfo {
+    NodeInfo {
+        // Nodes registered before endpoints were validated may not parse
+        endpoint: NodeEndpoint::parse(&ip).ok(),
+        ip,
+        address: node.secret_address,
+        score: node.score,
+        profile: node.profile,
+    }
+}
+
+// Walks the rank list from `start`, stopping at the first node under min_score since everything after it is lower
+pub fn get_top_nodes<'a, S: Storage>(store: &'a S, start: u64, size: u64, min_score: u32) -> Vec<NodeInfo> {
+    let end = cmp::min(start.saturating_add(size), get_node_size(store));
+    let mut nodes = vec![];
+
+    for rank in start..end {
+        let ip = load_rank(store, rank);
+        let node = load_node_data(store, ip.clone());
+        if node.score < min_score {
+            break;
+        }
+        nodes.push(node_info(ip, node));
+    }
+
+    nodes
+}
+
+// Rank of the node at `ip`, for resuming a paginated walk of the node list after it
+pub fn node_rank<'a, S: Storage>(store: &'a S, ip: &str) -> Option<u64> {
+    bucket_read(NODE_RANK_POS_LOCATION, store).may_load(ip.as_bytes()).unwrap_or(None)
+}
+
+fn save_rank<'a, S: Storage>(store: &'a mut S, rank: u64, ip: &str) {
+    let bucket_response = bucket(NODE_RANK_LOCATION, store).save(rank.to_string().as_bytes(), &ip.to_string());
+    match bucket_response {
+        Ok(bucket_response) => bucket_response,
+        Err(e) => panic!("Bucket Error: {}", e)
+    }
+    let bucket_response = bucket(NODE_RANK_POS_LOCATION, store).save(ip.as_bytes(), &rank);
+    match bucket_response {
+        Ok(bucket_response) => bucket_response,
+        Err(e) => panic!("Bucket Error: {}", e)
+    }
+}
+
+fn load_rank<'a, S: Storage>(store: &'a S, rank: u64) -> String {
+    bucket_read(NODE_RANK_LOCATION, store).load(rank.to_string().as_bytes()).unwrap()
+}
+
+fn load_rank_pos<'a, S: Storage>(store: &'a S, ip: &str) -> u64 {
+    bucket_read(NODE_RANK_POS_LOCATION, store).load(ip.as_bytes()).unwrap()
+}
+
+// Moves the node at `rank` up or down until the rank list is sorted again. Ties keep their current order.
+fn resort_rank<'a, S: Storage>(store: &'a mut S, rank: u64, score: u32) {
+    let size = get_node_size(store);
+    let ip = load_rank(store, rank);
+    let mut rank = rank;
+
+    while rank > 0 {
+        let above = load_rank(store, rank - 1);
+        if load_node_data(store, above.clone()).score >= score {
+            break;
+        }
+        save_rank(store, rank, &above);
+        rank -= 1;
+    }
+
+    while rank + 1 < size {
+        let below = load_rank(store, rank + 1);
+        if load_node_data(store, below.clone()).score <= score {
+            break;
+        }
+        save_rank(store, rank, &below);
+        rank += 1;
+    }
+
+    save_rank(store, rank, &ip);
+}
+
+// Swap-remove: the last node takes the removed node's slot so the list stays packed from 0 to size - 1
+pub fn remove_node<'a, S: Storage>(store: &'a mut S, ip: String) {
+    let size = get_node_size(store);
+    let index = load_node_index(store, &ip);
+    let last = size - 1;
+
+    if index != last {
+        let last_ip = load_node_loc(store, last.to_string());
+        save_node_loc(store, index.to_string(), last_ip.clone());
+        save_node_index(store, last_ip, index);
+    }
+
+    let node = load_node_data(store, ip.clone());
+
+    // The last-ranked node takes the removed node's rank too, then sinks back down. It stops at the first
+    // node that doesn't outscore it, rather than every node under the removed one moving up a rank.
+    let rank = load_rank_pos(store, &ip);
+    let last_ranked = load_rank(store, last);
+    bucket::<S, String>(NODE_RANK_LOCATION, store).remove(last.to_string().as_bytes());
+    bucket::<S, u64>(NODE_RANK_POS_LOCATION, store).remove(ip.as_bytes());
+
+    bucket::<S, String>(NODE_LOC_LOCATION, store).remove(last.to_string().as_bytes());
+    bucket::<S, u64>(NODE_INDEX_LOCATION, store).remove(ip.as_bytes());
+    bucket::<S, NodeData>(NODE_LOCATION, store).remove(ip.as_bytes());
+    bucket::<S, String>(NODE_OWNER_LOCATION, store).remove(node.secret_address.as_bytes());
+    remove_node_host(store, &ip);
+
+    set_node_size(store, last);
+
+    if rank != last {
+        save_rank(store, rank, &last_ranked);
+        let score = load_node_data(store, last_ranked).score;
+        resort_rank(store, rank, score);
+    }
+}
+
+// Moves a node to a new ip while keeping its slot in the list
+pub fn change_node_ip<'a, S: Storage>(store: &'a mut S, old_ip: String, new_ip: String) {
+    let index = load_node_index(store, &old_ip);
+    let node = load_node_data(store, old_ip.clone());
+    let rank = load_rank_pos(store, &old_ip);
+
+    save_rank(store, rank, &new_ip);
+    bucket::<S, u64>(NODE_RANK_POS_LOCATION, store).remove(old_ip.as_bytes());
+
+    save_node_loc(store, index.to_string(), new_ip.clone());
+    save_node_index(store, new_ip.clone(), index);
+    save_node_data(store, new_ip.clone(), node.clone());
+    remove_node_host(store, &old_ip);
+    save_node_host(store, &new_ip);
+    save_node_owner(store, node.secret_address, new_ip);
+
+    bucket::<S, u64>(NODE_INDEX_LOCATION, store).remove(old_ip.as_bytes());
+    bucket::<S, NodeData>(NODE_LOCATION, store).remove(old_ip.as_bytes());
+}
+
+// The ip of the node registered at the same host as `ip`, whatever port or format it was registered with
+pub fn node_at_host<S: Storage>(store: &S, ip: &str) -> Option<String> {
+    let host = endpoint_host(ip).ok()?;
+    bucket_read(NODE_HOST_LOCATION, store).may_load(host.as_bytes()).unwrap_or(None)
+}
+
+// Nodes registered before endpoints were validated may not parse, and aren't indexed
+fn save_node_host<S: Storage>(store: &mut S, ip: &str) {
+    if let Ok(host) = endpoint_host(ip) {
+        let bucket_response = bucket(NODE_HOST_LOCATION, store).save(host.as_bytes(), &ip.to_string());
+        match bucket_response {
+            Ok(bucket_response) => bucket_response,
+            Err(e) => panic!("Bucket Error: {}", e)
+        }
+    }
+}
+
+fn remove_node_host<S: Storage>(store: &mut S, ip: &str) {
+    if node_at_host(store, ip).as_deref() == Some(ip) {
+        if let Ok(host) = endpoint_host(ip) {
+            bucket::<S, String>(NODE_HOST_LOCATION, store).remove(host.as_bytes());
+        }
+    }
 }
 
 pub fn set_node_size<'a, S: Storage>( store: &'a mut S, size: u64 ) {
@@ -163,6 +458,18 @@ pub fn load_node_loc<'a, S: Storage>( store: &'a S, loc: String) -> String{
 }
 
 
+pub fn save_node_index<'a, S: Storage>( store: &'a mut S, ipaddress: String, index: u64 ) {
+    let bucket_response = bucket(NODE_INDEX_LOCATION, store).save(ipaddress.as_bytes(), &index);
+    match bucket_response {
+        Ok(bucket_response) => bucket_response,
+        Err(e) => panic!("Bucket Error: {}", e)
+    }
+}
+
+pub fn load_node_index<'a, S: Storage>( store: &'a S, ipaddress: &str) -> u64 {
+    bucket_read(NODE_INDEX_LOCATION, store).load(ipaddress.as_bytes()).unwrap()
+}
+
 pub fn save_node_data<'a, S: Storage>( store: &'a mut S, ipaddress: String, node_data: NodeData ) {
 
 
@@ -182,3 +489,153 @@ pub fn load_node_data<'a, S: Storage>( store: &'a S, ipaddress: String) -> NodeD
     bucket_read(NODE_LOCATION, store).load(ipaddress.as_bytes()).unwrap()
 }
 
+pub fn node_exists<'a, S: Storage>( store: &'a S, ipaddress: &str) -> bool {
+    let node: StdResult<Option<NodeData>> = bucket_read(NODE_LOCATION, store).may_load(ipaddress.as_bytes());
+    matches!(node, Ok(Some(_)))
+}
+
+pub fn save_node_owner<'a, S: Storage>( store: &'a mut S, address: String, ipaddress: String ) {
+    let bucket_response = bucket(NODE_OWNER_LOCATION, store).save(address.as_bytes(), &ipaddress);
+    match bucket_response {
+        Ok(bucket_response) => bucket_response,
+        Err(e) => panic!("Bucket Error: {}", e)
+    }
+}
+
+// Returns the ip of the node registered to this payout address, if there is one
+pub fn load_node_owner<'a, S: Storage>( store: &'a S, address: &str) -> Option<String> {
+    bucket_read(NODE_OWNER_LOCATION, store).may_load(address.as_bytes()).unwrap_or(None)
+}
+
+pub fn is_node_allowed<'a, S: Storage>( store: &'a S, address: &str) -> bool {
+    let allowed: StdResult<Option<bool>> = bucket_read(NODE_ALLOWLIST, store).may_load(address.as_bytes());
+    matches!(allowed, Ok(Some(true)))
+}
+
+pub fn try_update_node_allowlist<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    addresses: Vec<HumanAddr>,
+    allowed: bool,
+) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    for address in addresses {
+        let address = deps.api.human_address(&deps.api.canonical_address(&address)?)?;
+        if allowed {
+            bucket(NODE_ALLOWLIST, &mut deps.storage).save(address.as_str().as_bytes(), &true)?;
+        } else {
+            bucket::<S, bool>(NODE_ALLOWLIST, &mut deps.storage).remove(address.as_str().as_bytes());
+        }
+    }
+
+    Ok(HandleResponse::default())
+}
+
+fn signer_node<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<String> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    load_node_owner(&deps.storage, signer.as_str())
+        .ok_or_else(|| StdError::not_found("No node is registered to this address"))
+}
+
+pub fn try_update_node<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    new_ip: String,
+) -> StdResult<HandleResponse> {
+    let old_ip = signer_node(deps, &env)?;
+    let new_ip = normalize_endpoint(&new_ip)?;
+
+    if node_exists(&deps.storage, &new_ip) {
+        return Err(StdError::generic_err(format!("A node is already registered at {}", new_ip)));
+    }
+    // Moving to another port on the same host is fine
+    if let Some(existing) = node_at_host(&deps.storage, &new_ip).filter(|existing| existing != &old_ip) {
+        return Err(StdError::generic_err(format!("A node is already registered at {}", existing)));
+    }
+
+    change_node_ip(&mut deps.storage, old_ip, new_ip);
+
+    Ok(HandleResponse::default())
+}
+
+pub fn try_update_node_info<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    mut profile: NodeProfile,
+) -> StdResult<HandleResponse> {
+    let ip = signer_node(deps, &env)?;
+
+    if profile.free_bytes > profile.total_bytes {
+        return Err(StdError::generic_err("free_bytes can't be more than total_bytes"));
+    }
+    profile.region = profile.region.trim().to_lowercase();
+    if profile.region.len() > MAX_REGION_LEN {
+        return Err(StdError::generic_err(format!("region can be at most {} characters", MAX_REGION_LEN)));
+    }
+    if profile.protocols.len() > MAX_PROTOCOLS || profile.protocols.iter().any(|p| p.is_empty() || p.len() > MAX_PROTOCOL_LEN) {
+        return Err(StdError::generic_err(format!(
+            "A node can list at most {} protocols of 1 to {} characters each",
+            MAX_PROTOCOLS, MAX_PROTOCOL_LEN
+        )));
+    }
+
+    let mut node = load_node_data(&deps.storage, ip.clone());
+    node.profile = profile;
+    save_node_data(&mut deps.storage, ip, node);
+
+    Ok(HandleResponse::default())
+}
+
+// Walks the node list from the highest score down, starting at rank `start`, and returns up to `size`
+// nodes in `region` (any region if None) with at least `min_free_bytes` free. Stops after MAX_FIND_SCAN
+// ranks and also returns the ip of the last node looked at if the walk did not reach the end of the list
+pub fn find_nodes<'a, S: Storage>(store: &'a S, start: u64, size: u64, region: Option<String>, min_free_bytes: u64) -> (Vec<NodeInfo>, Option<String>) {
+    let region = region.map(|r| r.trim().to_lowercase());
+    let node_size = get_node_size(store);
+    let end = cmp::min(node_size, start.saturating_add(MAX_FIND_SCAN));
+    let mut nodes = vec![];
+    let mut rank = start;
+
+    while rank < end && (nodes.len() as u64) < size {
+        let ip = load_rank(store, rank);
+        rank += 1;
+        let node = load_node_data(store, ip.clone());
+        if node.profile.free_bytes < min_free_bytes {
+            continue;
+        }
+        if let Some(region) = &region {
+            if node.profile.region != *region {
+                continue;
+            }
+        }
+        nodes.push(node_info(ip, node));
+    }
+
+    let next = if rank > start && rank < node_size { Some(load_rank(store, rank - 1)) } else { None };
+    (nodes, next)
+}
+
+pub fn try_remove_node<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let ip = signer_node(deps, &env)?;
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    let score = refresh_score(&mut deps.storage, &ip, env.block.height)?;
+    stop_tracking(&mut deps.storage, signer.as_str(), score)?;
+    remove_node(&mut deps.storage, ip);
+    let stake = start_unbonding(&mut deps.storage, signer.as_str(), env.block.height)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("unbonding", stake.unbonding), log("release_height", stake.release_height)],
+        data: None,
+    })
+}
+
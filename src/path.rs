@@ -0,0 +1,52 @@
+use cosmwasm_std::{StdError, StdResult};
+
+// Segments in a path, counting the owner's address
+pub const MAX_PATH_DEPTH: usize = 32;
+pub const MAX_SEGMENT_LENGTH: usize = 255;
+// A parent reference could point into another namespace and would need resolving against the tree,
+// so it is refused rather than interpreted
+const RESERVED_NAMES: &[&str] = &[".."];
+
+/**
+  Turns a path from a message into the one form it is stored under. Empty and "." segments are dropped,
+  so `a//b/./c` is `a/b/c`, and so is a trailing '/'. Files and folders are addressed the same way, and
+  which one a path holds is only known from what is stored there. Errors if anything is left that isn't a
+  plain name.
+*/
+pub fn canonical_path(path: &str) -> StdResult<String> {
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty() && *segment != ".").collect();
+
+    if segments.is_empty() {
+        return Err(StdError::generic_err("Path is empty"));
+    }
+    if segments.len() > MAX_PATH_DEPTH {
+        return Err(StdError::generic_err(format!("Paths can be at most {} levels deep", MAX_PATH_DEPTH)));
+    }
+    for segment in &segments {
+        check_segment(segment)?;
+    }
+
+    Ok(segments.join("/"))
+}
+
+// The folder a canonical path is in, or None for a namespace's root folder
+pub fn parent_path(path: &str) -> Option<&str> {
+    path.rfind('/').map(|end| &path[..end])
+}
+
+pub fn canonical_paths(paths: Vec<String>) -> StdResult<Vec<String>> {
+    paths.iter().map(|path| canonical_path(path)).collect()
+}
+
+fn check_segment(segment: &str) -> StdResult<()> {
+    if segment.len() > MAX_SEGMENT_LENGTH {
+        return Err(StdError::generic_err(format!("Names can be at most {} bytes", MAX_SEGMENT_LENGTH)));
+    }
+    if segment.chars().any(|c| c.is_control() || c == '\\') {
+        return Err(StdError::generic_err("Names can't contain control characters or '\\'"));
+    }
+    if RESERVED_NAMES.contains(&segment) {
+        return Err(StdError::generic_err(format!("'{}' is reserved and can't be used as a name", segment)));
+    }
+    Ok(())
+}
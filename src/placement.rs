@@ -0,0 +1,277 @@
+use std::cmp;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{Api, Extern, HumanAddr, Querier, StdError, StdResult, Storage};
+use secret_toolkit::crypto::sha_256;
+
+use crate::backend::{bucket_load_readonly_file, file_key, get_namespace_from_path};
+use crate::challenge::file_root_id;
+use crate::msg::{PlacementInfo, UnderReplicatedResponse};
+use crate::nodes::load_node_owner;
+use crate::state::{load, State, CONFIG_KEY};
+
+static PLACEMENT_LOCATION: &[u8] = b"PLACEMENTS";
+static FILE_LIST_LOCATION: &[u8] = b"FILE_LIST";
+static FILE_LIST_SIZE_KEY: &[u8] = b"FILE_LIST_SIZE";
+static NODE_FILES_LOCATION: &[u8] = b"NODE_FILES";
+static NODE_FILE_COUNT_LOCATION: &[u8] = b"NODE_FILE_COUNT";
+static NODE_FILE_INDEX_LOCATION: &[u8] = b"NODE_FILE_INDEX";
+static FILE_ID_LOCATION: &[u8] = b"FILE_IDS";
+
+// Most files GetUnderReplicated looks at per call
+pub const MAX_PLACEMENT_PAGE: u64 = 100;
+
+// One entry per stored file, keyed by file_key. `nodes` are the addresses of nodes that confirmed
+// they hold the file's data. Nodes that have since left are skipped when counting replicas. `id` is
+// what nodes see instead of the file's namespace and path.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct Placement {
+    id: String,
+    nodes: Vec<String>,
+    list_index: u64,
+}
+
+fn file_list_size<S: Storage>(store: &S) -> u64 {
+    bucket_read(FILE_LIST_LOCATION, store).may_load(FILE_LIST_SIZE_KEY).unwrap_or(None).unwrap_or(0)
+}
+
+fn load_placement<S: Storage>(store: &S, key: &str) -> StdResult<Option<Placement>> {
+    bucket_read(PLACEMENT_LOCATION, store).may_load(key.as_bytes())
+}
+
+fn active_nodes<S: Storage>(store: &S, placement: &Placement) -> Vec<String> {
+    placement
+        .nodes
+        .iter()
+        .filter(|address| load_node_owner(store, address).is_some())
+        .cloned()
+        .collect()
+}
+
+// Addresses of the nodes currently holding the file at `key`
+pub fn file_nodes<S: Storage>(store: &S, key: &str) -> Vec<String> {
+    match load_placement(store, key) {
+        Ok(Some(placement)) => active_nodes(store, &placement),
+        _ => vec![],
+    }
+}
+
+// File keys a node has confirmed, kept as a list indexed by position like the file list, so a storage
+// challenge can be drawn from it without loading every file the node holds.
+fn node_entry_key(address: &str, entry: &str) -> String {
+    format!("{}/{}", address, entry)
+}
+
+pub fn node_file_count<S: Storage>(store: &S, address: &str) -> u64 {
+    bucket_read(NODE_FILE_COUNT_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(0)
+}
+
+pub fn node_file_at<S: Storage>(store: &S, address: &str, index: u64) -> StdResult<String> {
+    bucket_read(NODE_FILES_LOCATION, store).load(node_entry_key(address, &index.to_string()).as_bytes())
+}
+
+fn node_file_index<S: Storage>(store: &S, address: &str, key: &str) -> Option<u64> {
+    bucket_read(NODE_FILE_INDEX_LOCATION, store)
+        .may_load(node_entry_key(address, key).as_bytes())
+        .unwrap_or(None)
+}
+
+fn assign_file<S: Storage>(store: &mut S, address: &str, key: &str) -> StdResult<()> {
+    if node_file_index(store, address, key).is_some() {
+        return Ok(());
+    }
+    let count = node_file_count(store, address);
+    bucket(NODE_FILES_LOCATION, store).save(node_entry_key(address, &count.to_string()).as_bytes(), &key.to_string())?;
+    bucket(NODE_FILE_INDEX_LOCATION, store).save(node_entry_key(address, key).as_bytes(), &count)?;
+    bucket(NODE_FILE_COUNT_LOCATION, store).save(address.as_bytes(), &(count + 1))
+}
+
+// Swap-removes the file from each node's list
+fn unassign_files<S: Storage>(store: &mut S, nodes: &[String], key: &str) -> StdResult<()> {
+    for address in nodes {
+        let index = match node_file_index(store, address, key) {
+            Some(index) => index,
+            None => continue,
+        };
+        let last = node_file_count(store, address) - 1;
+        if index != last {
+            let last_key = node_file_at(store, address, last)?;
+            bucket(NODE_FILES_LOCATION, store).save(node_entry_key(address, &index.to_string()).as_bytes(), &last_key)?;
+            bucket(NODE_FILE_INDEX_LOCATION, store).save(node_entry_key(address, &last_key).as_bytes(), &index)?;
+        }
+        bucket::<S, String>(NODE_FILES_LOCATION, store).remove(node_entry_key(address, &last.to_string()).as_bytes());
+        bucket::<S, u64>(NODE_FILE_INDEX_LOCATION, store).remove(node_entry_key(address, key).as_bytes());
+        bucket(NODE_FILE_COUNT_LOCATION, store).save(address.as_bytes(), &last)?;
+    }
+    Ok(())
+}
+
+// Keyed with the contract seed, so an id can't be matched to a path by hashing guesses
+fn make_file_id<S: Storage>(store: &S, key: &str) -> StdResult<String> {
+    let state: State = load(store, CONFIG_KEY)?;
+    let mut bytes = state.prng_seed;
+    bytes.extend_from_slice(key.as_bytes());
+    Ok(hex::encode(sha_256(&bytes)))
+}
+
+pub fn file_key_of_id<S: Storage>(store: &S, id: &str) -> StdResult<String> {
+    bucket_read(FILE_ID_LOCATION, store)
+        .may_load(id.as_bytes())?
+        .ok_or_else(|| StdError::not_found("File"))
+}
+
+// Called whenever a file is written. New contents have to be confirmed again, so old confirmations are dropped.
+pub fn track_file<S: Storage>(store: &mut S, namespace: &str, path: &str) -> StdResult<()> {
+    let key = file_key(namespace, path);
+    let (id, list_index) = match load_placement(store, &key)? {
+        Some(placement) => {
+            unassign_files(store, &placement.nodes, &key)?;
+            (placement.id, placement.list_index)
+        }
+        None => {
+            let size = file_list_size(store);
+            bucket(FILE_LIST_LOCATION, store).save(size.to_string().as_bytes(), &key)?;
+            bucket(FILE_LIST_LOCATION, store).save(FILE_LIST_SIZE_KEY, &(size + 1))?;
+
+            let id = make_file_id(store, &key)?;
+            bucket(FILE_ID_LOCATION, store).save(id.as_bytes(), &key)?;
+            (id, size)
+        }
+    };
+
+    let placement = Placement {
+        id,
+        nodes: vec![],
+        list_index,
+    };
+    bucket(PLACEMENT_LOCATION, store).save(key.as_bytes(), &placement)
+}
+
+// Swap-removes the file from the list the repair query walks
+pub fn untrack_file<S: Storage>(store: &mut S, key: &str) -> StdResult<()> {
+    let placement = match load_placement(store, key)? {
+        Some(placement) => placement,
+        None => return Ok(()),
+    };
+    unassign_files(store, &placement.nodes, key)?;
+
+    let last = file_list_size(store) - 1;
+    if placement.list_index != last {
+        let last_key: String = bucket_read(FILE_LIST_LOCATION, store).load(last.to_string().as_bytes())?;
+        let mut last_placement: Placement = bucket_read(PLACEMENT_LOCATION, store).load(last_key.as_bytes())?;
+        last_placement.list_index = placement.list_index;
+        bucket(FILE_LIST_LOCATION, store).save(placement.list_index.to_string().as_bytes(), &last_key)?;
+        bucket(PLACEMENT_LOCATION, store).save(last_key.as_bytes(), &last_placement)?;
+    }
+    bucket::<S, String>(FILE_LIST_LOCATION, store).remove(last.to_string().as_bytes());
+    bucket(FILE_LIST_LOCATION, store).save(FILE_LIST_SIZE_KEY, &last)?;
+    bucket::<S, Placement>(PLACEMENT_LOCATION, store).remove(key.as_bytes());
+    bucket::<S, String>(FILE_ID_LOCATION, store).remove(placement.id.as_bytes());
+
+    Ok(())
+}
+
+// A moved file has the same contents, so the nodes holding it carry over to the new path
+pub fn copy_placement<S: Storage>(store: &mut S, from_key: &str, to_key: &str) -> StdResult<()> {
+    let from = load_placement(store, from_key)?;
+    let to = load_placement(store, to_key)?;
+    if let (Some(from), Some(mut to)) = (from, to) {
+        unassign_files(store, &to.nodes, to_key)?;
+        for address in &from.nodes {
+            assign_file(store, address, to_key)?;
+        }
+        to.nodes = from.nodes;
+        bucket(PLACEMENT_LOCATION, store).save(to_key.as_bytes(), &to)?;
+    }
+    Ok(())
+}
+
+// Errors unless `address` can still become a replica of the file at `key`
+pub fn check_replica<S: Storage>(store: &S, key: &str, address: &str) -> StdResult<()> {
+    let placement = load_placement(store, key)?.ok_or_else(|| StdError::not_found("File"))?;
+    if placement.nodes.iter().any(|node| node == address) {
+        return Err(StdError::generic_err("This node already confirmed this file"));
+    }
+    let state: State = load(store, CONFIG_KEY)?;
+    if active_nodes(store, &placement).len() as u64 >= state.config.replication_factor {
+        return Err(StdError::generic_err(format!(
+            "This file already has {} replicas",
+            state.config.replication_factor
+        )));
+    }
+    Ok(())
+}
+
+// Called once the node has passed the confirmation challenge for the file. Returns the number of replicas.
+pub fn add_replica<S: Storage>(store: &mut S, key: &str, address: &str) -> StdResult<u64> {
+    check_replica(store, key, address)?;
+    let mut placement = load_placement(store, key)?.ok_or_else(|| StdError::not_found("File"))?;
+
+    // Drop nodes that have left while we're rewriting the entry anyway
+    let active = active_nodes(store, &placement);
+    let departed: Vec<String> = placement.nodes.iter().filter(|node| !active.contains(node)).cloned().collect();
+    unassign_files(store, &departed, key)?;
+    placement.nodes = active;
+    placement.nodes.push(address.to_string());
+    bucket(PLACEMENT_LOCATION, store).save(key.as_bytes(), &placement)?;
+    assign_file(store, address, key)?;
+
+    Ok(placement.nodes.len() as u64)
+}
+
+fn placement_info<S: Storage>(store: &S, key: &str, placement: Placement) -> PlacementInfo {
+    PlacementInfo {
+        nodes: active_nodes(store, &placement),
+        root: file_root_id(store, key),
+        file: placement.id,
+    }
+}
+
+// Only readers of the file can see where it is stored, and its id
+pub fn query_placement<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    path: String,
+    behalf: &HumanAddr,
+) -> StdResult<PlacementInfo> {
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace).map_err(|_| StdError::not_found("File"))?;
+    if !file.can_read(behalf.to_string()) {
+        return Err(StdError::generic_err("Unauthorized to read this file"));
+    }
+
+    let key = file_key(&namespace, &path);
+    let placement = load_placement(&deps.storage, &key)?
+        .ok_or_else(|| StdError::generic_err("Folders aren't stored by nodes"))?;
+    Ok(placement_info(&deps.storage, &key, placement))
+}
+
+// Walks up to `limit` entries of the file list from `start` and returns those held by fewer than
+// replication_factor active nodes, by id. `next` is where the following call should start, if anywhere.
+pub fn query_under_replicated<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start: u64,
+    limit: u64,
+) -> StdResult<UnderReplicatedResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let size = file_list_size(&deps.storage);
+    let end = cmp::min(size, start.saturating_add(cmp::min(limit, MAX_PLACEMENT_PAGE)));
+
+    let mut files = vec![];
+    for i in start..end {
+        let key: String = bucket_read(FILE_LIST_LOCATION, &deps.storage).load(i.to_string().as_bytes())?;
+        let placement: Placement = bucket_read(PLACEMENT_LOCATION, &deps.storage).load(key.as_bytes())?;
+        if (active_nodes(&deps.storage, &placement).len() as u64) < state.config.replication_factor {
+            files.push(placement_info(&deps.storage, &key, placement));
+        }
+    }
+
+    Ok(UnderReplicatedResponse {
+        files,
+        next: if end < size { Some(end) } else { None },
+    })
+}
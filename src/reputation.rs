@@ -0,0 +1,270 @@
+use std::cmp;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{log, Api, Env, Extern, HandleResponse, Querier, StdError, StdResult, Storage};
+
+use crate::endpoint::normalize_endpoint;
+use crate::backend::{bucket_load_readonly_file, file_key, get_namespace_from_path};
+use crate::msg::ScoreHistoryResponse;
+use crate::nodes::{get_node, get_node_size, load_node_data, load_node_owner, node_exists, set_node_score, MAX_NODE_PAGE, STARTING_SCORE};
+use crate::placement::file_nodes;
+use crate::state::{load, State, CONFIG_KEY};
+
+static REPUTATION_LOCATION: &[u8] = b"REPUTATION";
+static SCORE_HISTORY_LOCATION: &[u8] = b"SCORE_HISTORY";
+static FAILURE_REPORT_LOCATION: &[u8] = b"FAILURE_REPORTS";
+
+pub const MAX_SCORE: u32 = 1000;
+pub const HEARTBEAT_BONUS: u32 = 5;
+pub const FAILURE_PENALTY: u32 = 25;
+// Lost every `score_decay_blocks` blocks, so a node that stops checking in slowly sinks down the list
+pub const DECAY_STEP: u32 = 10;
+// Only the latest events are kept per node
+pub const MAX_HISTORY: u64 = 50;
+
+// Reputation is keyed by the node's payout address so it follows the node through UpdateNode,
+// and through RemoveNode and InitNode: `left_score` is the score the node had when it was removed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct Reputation {
+    last_heartbeat: u64,
+    last_decay: u64,
+    history_len: u64,
+    #[serde(default)]
+    left_score: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScoreEvent {
+    pub height: u64,
+    pub score: u32,
+    pub reason: String,
+}
+
+fn load_reputation<S: Storage>(store: &S, address: &str) -> Reputation {
+    bucket_read(REPUTATION_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+fn save_reputation<S: Storage>(store: &mut S, address: &str, reputation: &Reputation) -> StdResult<()> {
+    bucket(REPUTATION_LOCATION, store).save(address.as_bytes(), reputation)
+}
+
+fn history_key(address: &str, index: u64) -> String {
+    format!("{}/{}", address, index % MAX_HISTORY)
+}
+
+// Called when a node registers so decay starts counting from now rather than from an older registration.
+// Returns the score the node starts with, which is the one it left with if it was registered before.
+pub fn start_tracking<S: Storage>(store: &mut S, address: &str, height: u64) -> StdResult<u32> {
+    let mut reputation = load_reputation(store, address);
+    reputation.last_heartbeat = height;
+    reputation.last_decay = height;
+    let score = reputation.left_score.take().unwrap_or(STARTING_SCORE);
+    save_reputation(store, address, &reputation)?;
+    Ok(score)
+}
+
+// Called by RemoveNode, after any pending decay is applied, so leaving doesn't reset a low score
+pub fn stop_tracking<S: Storage>(store: &mut S, address: &str, score: u32) -> StdResult<()> {
+    let mut reputation = load_reputation(store, address);
+    reputation.left_score = Some(score);
+    save_reputation(store, address, &reputation)
+}
+
+fn apply_decay(reputation: &mut Reputation, score: u32, height: u64, decay_blocks: u64) -> u32 {
+    if decay_blocks == 0 || height <= reputation.last_decay {
+        return score;
+    }
+
+    let periods = (height - reputation.last_decay) / decay_blocks;
+    reputation.last_decay += periods * decay_blocks;
+
+    let loss = cmp::min(periods.saturating_mul(DECAY_STEP as u64), u32::MAX as u64) as u32;
+    score.saturating_sub(loss)
+}
+
+fn record_event<S: Storage>(store: &mut S, address: &str, reputation: &mut Reputation, event: &ScoreEvent) -> StdResult<()> {
+    bucket(SCORE_HISTORY_LOCATION, store).save(history_key(address, reputation.history_len).as_bytes(), event)?;
+    reputation.history_len += 1;
+    save_reputation(store, address, reputation)
+}
+
+// Applies any pending decay, then the change, records it in the node's history and re-sorts the node list
+pub fn adjust_score<S: Storage>(
+    store: &mut S,
+    ip: &str,
+    height: u64,
+    change: i64,
+    reason: &str,
+) -> StdResult<u32> {
+    let config: State = load(store, CONFIG_KEY)?;
+    let node = load_node_data(store, ip.to_string());
+    let address = node.get_secret_address().to_string();
+
+    let mut reputation = load_reputation(store, &address);
+    let score = apply_decay(&mut reputation, node.get_score(), height, config.config.score_decay_blocks) as i64;
+    let score = cmp::max(0, cmp::min(MAX_SCORE as i64, score + change)) as u32;
+
+    record_event(store, &address, &mut reputation, &ScoreEvent { height, score, reason: reason.to_string() })?;
+    set_node_score(store, ip.to_string(), score);
+
+    Ok(score)
+}
+
+// Applies whatever decay the node has built up and nothing else. Returns the node's score.
+pub fn refresh_score<S: Storage>(store: &mut S, ip: &str, height: u64) -> StdResult<u32> {
+    let config: State = load(store, CONFIG_KEY)?;
+    let node = load_node_data(store, ip.to_string());
+    let address = node.get_secret_address().to_string();
+
+    let mut reputation = load_reputation(store, &address);
+    let score = apply_decay(&mut reputation, node.get_score(), height, config.config.score_decay_blocks);
+    if score == node.get_score() {
+        save_reputation(store, &address, &reputation)?;
+        return Ok(score);
+    }
+
+    record_event(store, &address, &mut reputation, &ScoreEvent { height, score, reason: String::from("decay") })?;
+    set_node_score(store, ip.to_string(), score);
+
+    Ok(score)
+}
+
+// Queries can't see the block height, so scores only decay when a handle touches them. Anyone can run this over
+// up to `limit` nodes from `start` in the node list, so silent nodes sink down the list without doing anything.
+pub fn try_decay_scores<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    start: u64,
+    limit: u64,
+) -> StdResult<HandleResponse> {
+    let size = get_node_size(&deps.storage);
+    let end = cmp::min(size, start.saturating_add(cmp::min(limit, MAX_NODE_PAGE)));
+
+    let mut decayed: u64 = 0;
+    for index in start..end {
+        // The index list doesn't move when scores re-sort the rank list
+        let ip = match get_node(&deps.storage, index) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        let before = load_node_data(&deps.storage, ip.clone()).get_score();
+        if refresh_score(&mut deps.storage, &ip, env.block.height)? != before {
+            decayed += 1;
+        }
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("decayed", decayed),
+            log("next", if end < size { end.to_string() } else { String::from("none") }),
+        ],
+        data: None,
+    })
+}
+
+pub fn try_heartbeat<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let ip = load_node_owner(&deps.storage, signer.as_str())
+        .ok_or_else(|| StdError::not_found("No node is registered to this address"))?;
+
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    let mut reputation = load_reputation(&deps.storage, signer.as_str());
+    if env.block.height < reputation.last_heartbeat + config.config.heartbeat_interval {
+        return Err(StdError::generic_err(format!(
+            "Heartbeats are counted once every {} blocks",
+            config.config.heartbeat_interval
+        )));
+    }
+    reputation.last_heartbeat = env.block.height;
+    save_reputation(&mut deps.storage, signer.as_str(), &reputation)?;
+
+    let score = adjust_score(&mut deps.storage, &ip, env.block.height, HEARTBEAT_BONUS as i64, "heartbeat")?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("node", ip), log("score", score)],
+        data: None,
+    })
+}
+
+// Anyone who can read the file can report a node placed on it that failed to serve it. A node can be reported
+// once per heartbeat interval for each file it holds, whoever reports it, so extra readers add nothing.
+pub fn try_report_failure<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    ip: String,
+    path: String,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let ip = normalize_endpoint(&ip)?;
+
+    if !node_exists(&deps.storage, &ip) {
+        return Err(StdError::not_found(format!("Node {}", ip)));
+    }
+    let node = load_node_data(&deps.storage, ip.clone());
+    if node.get_secret_address() == signer.as_str() {
+        return Err(StdError::generic_err("You can't report your own node"));
+    }
+
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    let file = bucket_load_readonly_file(&deps.storage, &path, &namespace)
+        .map_err(|_| StdError::not_found("File"))?;
+    if !file.can_read(signer.to_string()) {
+        return Err(StdError::generic_err("You can only report failures for files you can read"));
+    }
+    if !file_nodes(&deps.storage, &file_key(&namespace, &path)).iter().any(|address| address == node.get_secret_address()) {
+        return Err(StdError::generic_err("This node isn't holding this file"));
+    }
+
+    let config: State = load(&deps.storage, CONFIG_KEY)?;
+    let report_key = format!("{}|{}", node.get_secret_address(), file_key(&namespace, &path));
+    let last_report: Option<u64> = bucket_read(FAILURE_REPORT_LOCATION, &deps.storage).may_load(report_key.as_bytes())?;
+    if let Some(height) = last_report {
+        if env.block.height < height + config.config.heartbeat_interval {
+            return Err(StdError::generic_err("This node was already reported for this file recently"));
+        }
+    }
+    bucket(FAILURE_REPORT_LOCATION, &mut deps.storage).save(report_key.as_bytes(), &env.block.height)?;
+
+    let score = adjust_score(&mut deps.storage, &ip, env.block.height, -(FAILURE_PENALTY as i64), "failure_report")?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("node", ip), log("score", score)],
+        data: None,
+    })
+}
+
+pub fn query_score_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    ip: String,
+) -> StdResult<ScoreHistoryResponse> {
+    let ip = normalize_endpoint(&ip)?;
+    if !node_exists(&deps.storage, &ip) {
+        return Err(StdError::not_found(format!("Node {}", ip)));
+    }
+    let node = load_node_data(&deps.storage, ip);
+    let address = node.get_secret_address();
+    let reputation = load_reputation(&deps.storage, address);
+
+    let start = reputation.history_len.saturating_sub(MAX_HISTORY);
+    let mut history = vec![];
+    for i in start..reputation.history_len {
+        history.push(bucket_read(SCORE_HISTORY_LOCATION, &deps.storage).load(history_key(address, i).as_bytes())?);
+    }
+
+    Ok(ScoreHistoryResponse {
+        score: node.get_score(),
+        last_heartbeat: reputation.last_heartbeat,
+        history,
+    })
+}
@@ -0,0 +1,301 @@
+use std::cmp;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{
+    log, to_binary, Api, BankMsg, Coin, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, Querier, StdError,
+    StdResult, Storage, Uint128, WasmMsg,
+};
+
+use crate::admin::check_admin;
+use crate::epoch::{epoch_at, proof_count, prover_at, prover_count, was_slashed, EPOCH_LENGTH};
+use crate::msg::{EpochPayoutsResponse, RewardPoolResponse};
+use crate::nodes::{add_coins, burn_coins, load_node_owner};
+use crate::reputation::refresh_score;
+use crate::staking::load_slashed_stake;
+use crate::state::{load, save, RewardSource, State, CONFIG_KEY};
+
+// Native coins the contract is holding for node payouts
+pub static REWARD_POOL_KEY: &[u8] = b"reward_pool";
+// The oldest epoch DistributeEpoch hasn't paid out yet
+pub static NEXT_EPOCH_KEY: &[u8] = b"next_epoch";
+// How far DistributeEpoch has got through the epoch it is paying out
+static DISTRIBUTION_KEY: &[u8] = b"epoch_distribution";
+
+static PAYOUT_HISTORY_LOCATION: &[u8] = b"EPOCH_PAYOUTS";
+static PAYOUT_COUNT_LOCATION: &[u8] = b"EPOCH_PAYOUT_COUNT";
+static WEIGHT_LOCATION: &[u8] = b"EPOCH_WEIGHTS";
+
+// Only the latest payouts are kept per node
+pub const MAX_PAYOUT_HISTORY: u64 = 50;
+// Most provers one DistributeEpoch call goes through
+pub const MAX_DISTRIBUTE_PAGE: u64 = 100;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct EpochPayout {
+    pub epoch: u64,
+    pub proofs: u64,
+    pub score: u32,
+    pub credits: u32,
+}
+
+// Provers are weighed before anyone is paid, since every share depends on the total. The emission is
+// fixed when an epoch's distribution starts, so changing the config halfway can't overpay it.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+struct EpochDistribution {
+    epoch: u64,
+    emission: u32,
+    weighed: u64,
+    paid: u64,
+    total: Uint128,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+struct ProverWeight {
+    proofs: u64,
+    score: u32,
+}
+
+// Only the part of the SNIP-20 interface we call
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Snip20Msg {
+    Mint {
+        recipient: HumanAddr,
+        amount: Uint128,
+        padding: Option<String>,
+    },
+}
+
+pub fn load_reward_pool<S: Storage>(store: &S) -> Uint128 {
+    load(store, REWARD_POOL_KEY).unwrap_or_default()
+}
+
+pub fn save_reward_pool<S: Storage>(store: &mut S, balance: Uint128) -> StdResult<()> {
+    save(store, REWARD_POOL_KEY, &balance)
+}
+
+// Admin only. Everything sent along has to be in the native reward denom so nothing gets stuck in the contract.
+pub fn try_fund_reward_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    let denom = match &state.config.reward_source {
+        RewardSource::Native { denom } => denom,
+        RewardSource::Snip20 { .. } => {
+            return Err(StdError::generic_err("Rewards are minted as SNIP-20 tokens, there is no pool to fund"))
+        }
+    };
+
+    let mut amount: u128 = 0;
+    for coin in &env.message.sent_funds {
+        if coin.denom != *denom {
+            return Err(StdError::generic_err(format!("The reward pool only accepts {}", denom)));
+        }
+        amount += coin.amount.u128();
+    }
+    if amount == 0 {
+        return Err(StdError::generic_err(format!("No {} was sent to fund the reward pool", denom)));
+    }
+
+    let balance = Uint128(load_reward_pool(&deps.storage).u128() + amount);
+    save_reward_pool(&mut deps.storage, balance)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("funded", amount), log("balance", balance)],
+        data: None,
+    })
+}
+
+// Burns reward credits from the signer and pays out `reward_per_credit` for each one
+pub fn try_withdraw_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: u32,
+) -> StdResult<HandleResponse> {
+    if amount == 0 {
+        return Err(StdError::generic_err("Nothing to withdraw"));
+    }
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    let payout = (amount as u128)
+        .checked_mul(state.config.reward_per_credit.u128())
+        .ok_or_else(|| StdError::generic_err("Reward payout overflow"))?;
+    // Checked before anything is written so a failed withdrawal leaves the credits alone
+    let pool = load_reward_pool(&deps.storage).u128();
+    if let RewardSource::Native { .. } = state.config.reward_source {
+        if payout > pool {
+            return Err(StdError::generic_err("The reward pool can't cover this withdrawal right now"));
+        }
+    }
+    let remaining = burn_coins(&mut deps.storage, signer.as_str(), amount)?;
+
+    let message = match state.config.reward_source {
+        RewardSource::Native { denom } => {
+            save_reward_pool(&mut deps.storage, Uint128(pool - payout))?;
+
+            CosmosMsg::Bank(BankMsg::Send {
+                from_address: env.contract.address,
+                to_address: signer.clone(),
+                amount: vec![Coin { denom, amount: Uint128(payout) }],
+            })
+        }
+        RewardSource::Snip20 { contract_addr, code_hash } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr,
+            callback_code_hash: code_hash,
+            msg: to_binary(&Snip20Msg::Mint {
+                recipient: signer.clone(),
+                amount: Uint128(payout),
+                padding: None,
+            })?,
+            send: vec![],
+        }),
+    };
+
+    Ok(HandleResponse {
+        messages: vec![message],
+        log: vec![
+            log("withdrawn", amount),
+            log("payout", payout),
+            log("credits", remaining),
+        ],
+        data: None,
+    })
+}
+
+pub fn query_reward_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<RewardPoolResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+
+    Ok(RewardPoolResponse {
+        source: state.config.reward_source,
+        balance: load_reward_pool(&deps.storage),
+        reward_per_credit: state.config.reward_per_credit,
+        slashed_stake: load_slashed_stake(&deps.storage),
+    })
+}
+
+fn payout_count<S: Storage>(store: &S, address: &str) -> u64 {
+    bucket_read(PAYOUT_COUNT_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or(0)
+}
+
+fn payout_key(address: &str, index: u64) -> String {
+    format!("{}/{}", address, index % MAX_PAYOUT_HISTORY)
+}
+
+fn record_payout<S: Storage>(store: &mut S, address: &str, payout: &EpochPayout) -> StdResult<()> {
+    let count = payout_count(store, address);
+    bucket(PAYOUT_HISTORY_LOCATION, store).save(payout_key(address, count).as_bytes(), payout)?;
+    bucket(PAYOUT_COUNT_LOCATION, store).save(address.as_bytes(), &(count + 1))
+}
+
+// Pays out the oldest epoch that hasn't been paid yet, once it has ended. epoch_emission credits are split
+// between the nodes that passed storage challenges in it, weighted by challenges passed times current score.
+// A node gets at most one challenge per challenge_window blocks, and passing one earns nothing else, so this
+// emission is the only way challenges turn into credits.
+// Nodes slashed in the epoch or removed since get nothing, and rounding dust isn't handed out.
+// Anyone can call this. Each call goes through up to `limit` provers, first weighing them all and then paying
+// them, and the epoch counts as paid once the last one is; epochs that were missed are caught up the same way.
+pub fn try_distribute_epoch<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    limit: u64,
+) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    // Contracts set up before epochs existed start with the last finished one
+    let epoch = load(&deps.storage, NEXT_EPOCH_KEY).unwrap_or_else(|_| epoch_at(env.block.height).saturating_sub(1));
+    if epoch >= epoch_at(env.block.height) {
+        return Err(StdError::generic_err(format!("Epoch {} ends at block {}", epoch, (epoch + 1) * EPOCH_LENGTH)));
+    }
+
+    let mut progress = load(&deps.storage, DISTRIBUTION_KEY)
+        .ok()
+        .filter(|progress: &EpochDistribution| progress.epoch == epoch)
+        .unwrap_or(EpochDistribution { epoch, emission: state.config.epoch_emission, ..EpochDistribution::default() });
+    let count = prover_count(&deps.storage, epoch);
+    let limit = cmp::min(limit, MAX_DISTRIBUTE_PAGE);
+    let mut steps = 0;
+
+    while progress.weighed < count && steps < limit {
+        let address = prover_at(&deps.storage, epoch, progress.weighed)?;
+        progress.weighed += 1;
+        steps += 1;
+        if was_slashed(&deps.storage, &address, epoch) {
+            continue;
+        }
+        let ip = match load_node_owner(&deps.storage, &address) {
+            Some(ip) => ip,
+            None => continue,
+        };
+        let proofs = proof_count(&deps.storage, &address, epoch);
+        // Pending decay counts, so a prover that stopped checking in isn't weighed at a stale score
+        let score = refresh_score(&mut deps.storage, &ip, env.block.height)?;
+        bucket(WEIGHT_LOCATION, &mut deps.storage).save(weight_key(epoch, &address).as_bytes(), &ProverWeight { proofs, score })?;
+        progress.total = Uint128(progress.total.u128() + proofs as u128 * score as u128);
+    }
+
+    let mut distributed: u32 = 0;
+    while progress.weighed == count && progress.paid < count && steps < limit {
+        let address = prover_at(&deps.storage, epoch, progress.paid)?;
+        progress.paid += 1;
+        steps += 1;
+        let key = weight_key(epoch, &address);
+        let weight: Option<ProverWeight> = bucket_read(WEIGHT_LOCATION, &deps.storage).may_load(key.as_bytes())?;
+        let ProverWeight { proofs, score } = match weight {
+            Some(weight) => weight,
+            None => continue,
+        };
+        bucket::<S, ProverWeight>(WEIGHT_LOCATION, &mut deps.storage).remove(key.as_bytes());
+        if progress.total.u128() == 0 {
+            continue;
+        }
+        let credits = (progress.emission as u128 * proofs as u128 * score as u128 / progress.total.u128()) as u32;
+        add_coins(&mut deps.storage, &address, credits)?;
+        record_payout(&mut deps.storage, &address, &EpochPayout { epoch, proofs, score, credits })?;
+        distributed += credits;
+    }
+
+    save(&mut deps.storage, DISTRIBUTION_KEY, &progress)?;
+    let remaining = (count - progress.weighed) + (count - progress.paid);
+    if remaining == 0 {
+        save(&mut deps.storage, NEXT_EPOCH_KEY, &(epoch + 1))?;
+    }
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("epoch", epoch), log("distributed", distributed), log("remaining", remaining)],
+        data: None,
+    })
+}
+
+fn weight_key(epoch: u64, address: &str) -> String {
+    format!("{}/{}", epoch, address)
+}
+
+pub fn query_epoch_payouts<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<EpochPayoutsResponse> {
+    let count = payout_count(&deps.storage, address.as_str());
+    let start = count.saturating_sub(MAX_PAYOUT_HISTORY);
+    let mut payouts = vec![];
+    for i in start..count {
+        payouts.push(bucket_read(PAYOUT_HISTORY_LOCATION, &deps.storage).load(payout_key(address.as_str(), i).as_bytes())?);
+    }
+
+    Ok(EpochPayoutsResponse {
+        next_epoch: load(&deps.storage, NEXT_EPOCH_KEY).ok(),
+        payouts,
+    })
+}
@@ -0,0 +1,45 @@
+use std::cmp;
+
+use cosmwasm_std::{Api, Extern, Querier, StdResult, Storage};
+use secret_toolkit::crypto::Prng;
+
+use crate::msg::NodeListResponse;
+use crate::nodes::{get_top_nodes, MAX_NODE_PAGE};
+use crate::state::{load, State, CONFIG_KEY};
+use crate::utils::random_u64;
+
+// Picks `count` distinct nodes with odds proportional to their score. The draw is seeded from the
+// contract seed and `file_hint`, so the same hint gives the same nodes until scores or the node list change.
+// Only the top max_node_list_size nodes (at most 100) at or above min_select_score take part.
+pub fn query_select_nodes<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    count: u64,
+    file_hint: String,
+) -> StdResult<NodeListResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    let size = cmp::min(MAX_NODE_PAGE, state.config.max_node_list_size);
+    let mut candidates = get_top_nodes(&deps.storage, 0, size, state.config.min_select_score);
+    let count = cmp::min(count, candidates.len() as u64);
+
+    let mut rng = Prng::new(&state.prng_seed, file_hint.as_bytes());
+    let mut nodes = vec![];
+    for _ in 0..count {
+        let total: u64 = candidates.iter().map(|node| node.score as u64).sum();
+        if total == 0 {
+            break;
+        }
+
+        let mut target = random_u64(&mut rng) % total;
+        let mut picked = candidates.len() - 1;
+        for (i, node) in candidates.iter().enumerate() {
+            if target < node.score as u64 {
+                picked = i;
+                break;
+            }
+            target -= node.score as u64;
+        }
+        nodes.push(candidates.remove(picked));
+    }
+
+    Ok(NodeListResponse { nodes })
+}
@@ -0,0 +1,210 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{
+    log, Api, BankMsg, Coin, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult,
+    Storage, Uint128,
+};
+
+use crate::admin::check_admin;
+use crate::epoch::{epoch_at, record_slash};
+use crate::rewards::{load_reward_pool, save_reward_pool};
+use crate::state::{load, save, RewardSource, State, CONFIG_KEY};
+
+static STAKE_LOCATION: &[u8] = b"STAKES";
+// Slashed stake the reward pool can't take, because the rewards are SNIP-20 or in another denom
+pub static SLASHED_STAKE_KEY: &[u8] = b"slashed_stake";
+
+// Keyed by node address. Stake moves to `unbonding` when the node is removed and can be
+// withdrawn once `release_height` is reached. Both parts can be slashed until then.
+// `denom` is the stake_denom the coins were sent in, so changing it doesn't affect stake already held.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct Stake {
+    pub bonded: Uint128,
+    pub unbonding: Uint128,
+    pub release_height: u64,
+    pub denom: String,
+}
+
+pub fn load_stake<S: Storage>(store: &S, address: &str) -> Stake {
+    bucket_read(STAKE_LOCATION, store)
+        .may_load(address.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+fn save_stake<S: Storage>(store: &mut S, address: &str, stake: &Stake) -> StdResult<()> {
+    bucket(STAKE_LOCATION, store).save(address.as_bytes(), stake)
+}
+
+// Called by InitNode. Everything sent has to be in the stake denom and add up to at least min_node_stake.
+pub fn bond_stake<S: Storage>(store: &mut S, address: &str, sent_funds: &[Coin]) -> StdResult<Uint128> {
+    let state: State = load(store, CONFIG_KEY)?;
+
+    let mut amount: u128 = 0;
+    for coin in sent_funds {
+        if coin.denom != state.config.stake_denom {
+            return Err(StdError::generic_err(format!("Stake must be sent in {}", state.config.stake_denom)));
+        }
+        amount += coin.amount.u128();
+    }
+    if amount < state.config.min_node_stake.u128() {
+        return Err(StdError::generic_err(format!(
+            "Running a node requires a stake of at least {}{}",
+            state.config.min_node_stake, state.config.stake_denom
+        )));
+    }
+
+    let mut stake = load_stake(store, address);
+    if stake.bonded.u128() + stake.unbonding.u128() > 0 && stake.denom != state.config.stake_denom {
+        return Err(StdError::generic_err(format!(
+            "This address still has stake in {}. Withdraw it before staking in {}",
+            stake.denom, state.config.stake_denom
+        )));
+    }
+    stake.bonded = Uint128(stake.bonded.u128() + amount);
+    stake.denom = state.config.stake_denom;
+    save_stake(store, address, &stake)?;
+
+    Ok(stake.bonded)
+}
+
+// Called by RemoveNode. Adding to an existing unbonding amount restarts its delay.
+pub fn start_unbonding<S: Storage>(store: &mut S, address: &str, height: u64) -> StdResult<Stake> {
+    let state: State = load(store, CONFIG_KEY)?;
+
+    let mut stake = load_stake(store, address);
+    stake.unbonding = Uint128(stake.unbonding.u128() + stake.bonded.u128());
+    stake.bonded = Uint128(0);
+    stake.release_height = height + state.config.unbonding_period;
+    save_stake(store, address, &stake)?;
+
+    Ok(stake)
+}
+
+pub fn load_slashed_stake<S: Storage>(store: &S) -> Vec<Coin> {
+    load(store, SLASHED_STAKE_KEY).unwrap_or_default()
+}
+
+fn hold_slashed_stake<S: Storage>(store: &mut S, denom: &str, amount: u128) -> StdResult<()> {
+    let mut held = load_slashed_stake(store);
+    match held.iter_mut().find(|coin| coin.denom == denom) {
+        Some(coin) => coin.amount = Uint128(coin.amount.u128() + amount),
+        None => held.push(Coin { denom: denom.to_string(), amount: Uint128(amount) }),
+    }
+    save(store, SLASHED_STAKE_KEY, &held)
+}
+
+// Takes `percent` of the bonded and unbonding stake. It goes into the reward pool when that pays out
+// the stake's denom, otherwise it is held for the admin to withdraw with WithdrawSlashedStake.
+// The node also forfeits its escrow payments for the epoch it was slashed in.
+pub fn slash_stake<S: Storage>(store: &mut S, address: &str, percent: u64, height: u64) -> StdResult<Uint128> {
+    record_slash(store, address, epoch_at(height))?;
+
+    let mut stake = load_stake(store, address);
+
+    let bonded_cut = stake.bonded.u128() * percent as u128 / 100;
+    let unbonding_cut = stake.unbonding.u128() * percent as u128 / 100;
+    stake.bonded = Uint128(stake.bonded.u128() - bonded_cut);
+    stake.unbonding = Uint128(stake.unbonding.u128() - unbonding_cut);
+    save_stake(store, address, &stake)?;
+
+    let slashed = bonded_cut + unbonding_cut;
+    if slashed > 0 {
+        let state: State = load(store, CONFIG_KEY)?;
+        match state.config.reward_source {
+            RewardSource::Native { denom } if denom == stake.denom => {
+                let pool = load_reward_pool(store).u128();
+                save_reward_pool(store, Uint128(pool + slashed))?;
+            }
+            _ => hold_slashed_stake(store, &stake.denom, slashed)?,
+        }
+    }
+
+    Ok(Uint128(slashed))
+}
+
+pub fn try_withdraw_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+
+    let mut stake = load_stake(&deps.storage, signer.as_str());
+    if stake.unbonding.u128() == 0 {
+        return Err(StdError::generic_err("No stake is unbonding"));
+    }
+    if env.block.height < stake.release_height {
+        return Err(StdError::generic_err(format!("Stake is unbonding until block {}", stake.release_height)));
+    }
+
+    let amount = stake.unbonding;
+    stake.unbonding = Uint128(0);
+    save_stake(&mut deps.storage, signer.as_str(), &stake)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address,
+            to_address: signer,
+            amount: vec![Coin { denom: stake.denom, amount }],
+        })],
+        log: vec![log("withdrawn", amount)],
+        data: None,
+    })
+}
+
+pub fn try_slash_node<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+    percent: u64,
+) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    if percent == 0 || percent > 100 {
+        return Err(StdError::generic_err("percent must be between 1 and 100"));
+    }
+
+    let slashed = slash_stake(&mut deps.storage, address.as_str(), percent, env.block.height)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("node", address), log("slashed", slashed)],
+        data: None,
+    })
+}
+
+// Admin only. Sends out all the slashed stake the reward pool couldn't take.
+pub fn try_withdraw_slashed_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    recipient: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    check_admin(&state, &env)?;
+
+    let held = load_slashed_stake(&deps.storage);
+    if held.is_empty() {
+        return Err(StdError::generic_err("No slashed stake is held"));
+    }
+    save(&mut deps.storage, SLASHED_STAKE_KEY, &Vec::<Coin>::new())?;
+
+    let withdrawn = held.iter().map(|coin| format!("{}{}", coin.amount, coin.denom)).collect::<Vec<String>>().join(",");
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address,
+            to_address: recipient,
+            amount: held,
+        })],
+        log: vec![log("withdrawn", withdrawn)],
+        data: None,
+    })
+}
+
+pub fn query_stake<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    address: HumanAddr,
+) -> StdResult<Stake> {
+    Ok(load_stake(&deps.storage, address.as_str()))
+}
@@ -7,7 +7,7 @@ use serde::de::DeserializeOwned;
 
 // use std::collections::HashMap;
 
-use cosmwasm_std::{Storage, HumanAddr, StdResult, StdError, ReadonlyStorage, CanonicalAddr};
+use cosmwasm_std::{Storage, HumanAddr, StdResult, StdError, ReadonlyStorage, CanonicalAddr, Uint128};
 use cosmwasm_storage::{ReadonlyPrefixedStorage, PrefixedStorage};
 
 use crate::viewing_key::ViewingKey;
@@ -22,7 +22,118 @@ pub const PREFIX_VIEWING_KEY: &[u8] = b"viewingkey";
 #[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
 pub struct State {
     pub owner: HumanAddr,
+    pub pending_owner: Option<HumanAddr>,
     pub prng_seed: Vec<u8>,
+    pub status: ContractStatus,
+    pub pause: PauseMatrix,
+    pub config: Config,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    Normal,
+    StopWrites,
+    StopAll,
+}
+
+// Each category can be paused on its own with HandleMsg::SetPause
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct PauseMatrix {
+    pub file_writes: bool,
+    pub permission_changes: bool,
+    pub node_registration: bool,
+    pub reward_claims: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    FileWrites,
+    PermissionChanges,
+    NodeRegistration,
+    RewardClaims,
+}
+
+impl PauseMatrix {
+    pub fn is_paused(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::FileWrites => self.file_writes,
+            Operation::PermissionChanges => self.permission_changes,
+            Operation::NodeRegistration => self.node_registration,
+            Operation::RewardClaims => self.reward_claims,
+        }
+    }
+}
+
+// Tunables the admin can change with HandleMsg::UpdateConfig
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub max_contents_size: u64,
+    pub max_multi_size: u64,
+    pub max_node_list_size: u64,
+    pub node_allowlist_enabled: bool,
+    pub heartbeat_interval: u64,
+    pub score_decay_blocks: u64,
+    pub challenge_window: u64,
+    pub reward_source: RewardSource,
+    // Paid out for each reward credit burned by WithdrawRewards
+    pub reward_per_credit: Uint128,
+    // Native stake nodes lock up in InitNode. Slashed stake goes to a native reward pool, so this has to match
+    // its denom. With SNIP-20 rewards it is held for the admin instead.
+    pub min_node_stake: Uint128,
+    pub stake_denom: String,
+    pub unbonding_period: u64,
+    // Share of stake lost on a failed or expired storage challenge
+    pub slash_percent: u64,
+    // Nodes that should confirm each file before it stops showing up in GetUnderReplicated
+    pub replication_factor: u64,
+    // Nodes scoring below this are never handed out by SelectNodes
+    pub min_select_score: u32,
+    // Epochs a storage payment sent with Create is spread over, and the native denom it must be sent in
+    pub escrow_epochs: u64,
+    pub payment_denom: String,
+    // The least storage payment each new file needs. CreateMulti and Batch split theirs over the files they create.
+    pub write_fee: Uint128,
+    // Reward credits DistributeEpoch shares out for each epoch
+    pub epoch_emission: u32,
+    // Blocks an upload session stays open after its last chunk
+    pub upload_window: u64,
+}
+
+// Native rewards come out of the pool the admin fills with FundRewardPool.
+// SNIP-20 rewards are minted, so the contract has to be a minter on that token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RewardSource {
+    Native { denom: String },
+    Snip20 { contract_addr: HumanAddr, code_hash: String },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_contents_size: 64 * 1024,
+            max_multi_size: 50,
+            max_node_list_size: 100,
+            node_allowlist_enabled: false,
+            heartbeat_interval: 100,
+            score_decay_blocks: 1000,
+            challenge_window: 50,
+            reward_source: RewardSource::Native { denom: String::from("uscrt") },
+            reward_per_credit: Uint128(1000),
+            min_node_stake: Uint128(1_000_000),
+            stake_denom: String::from("uscrt"),
+            unbonding_period: 10_000,
+            slash_percent: 10,
+            replication_factor: 3,
+            min_select_score: 100,
+            escrow_epochs: 12,
+            payment_denom: String::from("uscrt"),
+            write_fee: Uint128(0),
+            epoch_emission: 1000,
+            upload_window: 600,
+        }
+    }
 }
 
 pub fn save<T: Serialize, S: Storage>(storage: &mut S, key: &[u8],value: &T) -> StdResult<()> {
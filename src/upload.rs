@@ -0,0 +1,257 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use cosmwasm_storage::{bucket, bucket_read};
+use cosmwasm_std::{log, to_binary, Api, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult, Storage};
+
+use crate::backend::{bucket_load_readonly_file, get_namespace_from_path, try_create_file};
+use crate::msg::HandleAnswer;
+use crate::path::parent_path;
+use crate::state::{load, save, State, CONFIG_KEY};
+
+static SESSION_LOCATION: &[u8] = b"UPLOAD_SESSIONS";
+static CHUNK_LOCATION: &[u8] = b"UPLOAD_CHUNKS";
+static OWNER_SESSIONS_LOCATION: &[u8] = b"UPLOAD_OWNERS";
+pub static SESSION_COUNT_KEY: &[u8] = b"upload_count";
+
+pub const MAX_UPLOAD_CHUNKS: u64 = 256;
+// Open sessions per address. Expired ones are cleared when the address begins a new one.
+pub const MAX_OPEN_UPLOADS: usize = 10;
+
+// A file being uploaded over several transactions. Nothing is written to the file tree until
+// CommitUpload, and a session nobody sends a chunk to for `upload_window` blocks expires.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct UploadSession {
+    pub owner: String,
+    pub path: String,
+    pub total_size: u64,
+    pub chunk_count: u64,
+    pub received: Vec<bool>,
+    pub received_size: u64,
+    pub expires_at: u64,
+}
+
+fn load_session<S: Storage>(store: &S, session: u64) -> StdResult<Option<UploadSession>> {
+    bucket_read(SESSION_LOCATION, store).may_load(session.to_string().as_bytes())
+}
+
+fn save_session<S: Storage>(store: &mut S, session: u64, upload: &UploadSession) -> StdResult<()> {
+    bucket(SESSION_LOCATION, store).save(session.to_string().as_bytes(), upload)
+}
+
+fn owner_sessions<S: Storage>(store: &S, owner: &str) -> Vec<u64> {
+    bucket_read(OWNER_SESSIONS_LOCATION, store)
+        .may_load(owner.as_bytes())
+        .unwrap_or(None)
+        .unwrap_or_default()
+}
+
+fn chunk_key(session: u64, index: u64) -> String {
+    format!("{}/{}", session, index)
+}
+
+fn delete_session<S: Storage>(store: &mut S, session: u64, upload: &UploadSession) -> StdResult<()> {
+    for index in 0..upload.chunk_count {
+        if upload.received[index as usize] {
+            bucket::<S, String>(CHUNK_LOCATION, store).remove(chunk_key(session, index).as_bytes());
+        }
+    }
+    bucket::<S, UploadSession>(SESSION_LOCATION, store).remove(session.to_string().as_bytes());
+
+    let mut sessions = owner_sessions(store, &upload.owner);
+    sessions.retain(|id| *id != session);
+    bucket(OWNER_SESSIONS_LOCATION, store).save(upload.owner.as_bytes(), &sessions)
+}
+
+// Loads a session the signer owns
+fn owned_session<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    session: u64,
+) -> StdResult<UploadSession> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let upload = load_session(&deps.storage, session)?.ok_or_else(|| StdError::not_found("Upload session"))?;
+    if upload.owner != signer.as_str() {
+        return Err(StdError::generic_err("This upload session belongs to someone else"));
+    }
+    Ok(upload)
+}
+
+fn check_open(upload: &UploadSession, env: &Env) -> StdResult<()> {
+    if env.block.height > upload.expires_at {
+        return Err(StdError::generic_err("This upload session has expired"));
+    }
+    Ok(())
+}
+
+pub fn try_begin_upload<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    path: String,
+    total_size: u64,
+    chunk_count: u64,
+) -> StdResult<HandleResponse> {
+    let signer = deps.api.human_address(&deps.api.canonical_address(&env.message.sender)?)?;
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+
+    if total_size > state.config.max_contents_size {
+        return Err(StdError::generic_err(format!("File contents are larger than the {} byte limit", state.config.max_contents_size)));
+    }
+    if chunk_count == 0 || chunk_count > MAX_UPLOAD_CHUNKS {
+        return Err(StdError::generic_err(format!("chunk_count must be between 1 and {}", MAX_UPLOAD_CHUNKS)));
+    }
+
+    // Fail now rather than at CommitUpload if the file couldn't be written anyway
+    let namespace = get_namespace_from_path(deps, path.clone()).unwrap_or(String::from("namespace does not exist!"));
+    if matches!(bucket_load_readonly_file(&deps.storage, &path, &namespace), Ok(file) if file.is_folder()) {
+        return Err(StdError::generic_err("Folders can't be uploaded"));
+    }
+    let parent = bucket_load_readonly_file(&deps.storage, &parent_path(&path).unwrap_or_default().to_string(), &namespace)
+        .map_err(|_| StdError::not_found("Parent folder"))?;
+    if !parent.is_folder() {
+        return Err(StdError::not_found("Parent folder"));
+    }
+    if !parent.can_write(signer.to_string()) {
+        return Err(StdError::generic_err("Sorry. You are unauthorized to create a file in this folder."));
+    }
+
+    let mut sessions = vec![];
+    for id in owner_sessions(&deps.storage, signer.as_str()) {
+        match load_session(&deps.storage, id)? {
+            Some(upload) if env.block.height > upload.expires_at => delete_session(&mut deps.storage, id, &upload)?,
+            Some(_) => sessions.push(id),
+            None => {}
+        }
+    }
+    if sessions.len() >= MAX_OPEN_UPLOADS {
+        return Err(StdError::generic_err(format!("At most {} uploads can be open at once", MAX_OPEN_UPLOADS)));
+    }
+
+    let session: u64 = load(&deps.storage, SESSION_COUNT_KEY).unwrap_or(0);
+    save(&mut deps.storage, SESSION_COUNT_KEY, &(session + 1))?;
+
+    let upload = UploadSession {
+        owner: signer.to_string(),
+        path,
+        total_size,
+        chunk_count,
+        received: vec![false; chunk_count as usize],
+        received_size: 0,
+        expires_at: env.block.height + state.config.upload_window,
+    };
+    save_session(&mut deps.storage, session, &upload)?;
+    sessions.push(session);
+    bucket(OWNER_SESSIONS_LOCATION, &mut deps.storage).save(signer.as_str().as_bytes(), &sessions)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("session", session), log("expires_at", upload.expires_at)],
+        data: Some(to_binary(&HandleAnswer::UploadSession { session })?),
+    })
+}
+
+// Sending a chunk again replaces it, so a client can resume by resending whatever it isn't sure arrived.
+// Every chunk pushes the expiry back.
+pub fn try_upload_chunk<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    session: u64,
+    index: u64,
+    data: String,
+) -> StdResult<HandleResponse> {
+    let mut upload = owned_session(deps, &env, session)?;
+    check_open(&upload, &env)?;
+    if index >= upload.chunk_count {
+        return Err(StdError::generic_err(format!("This upload only has {} chunks", upload.chunk_count)));
+    }
+
+    let key = chunk_key(session, index);
+    let replaced = if upload.received[index as usize] {
+        let old: String = bucket_read(CHUNK_LOCATION, &deps.storage).load(key.as_bytes())?;
+        old.len() as u64
+    } else {
+        0
+    };
+    let received_size = upload.received_size - replaced + data.len() as u64;
+    if received_size > upload.total_size {
+        return Err(StdError::generic_err(format!("Chunks add up to more than the {} bytes announced", upload.total_size)));
+    }
+
+    let state: State = load(&deps.storage, CONFIG_KEY)?;
+    bucket(CHUNK_LOCATION, &mut deps.storage).save(key.as_bytes(), &data)?;
+    upload.received[index as usize] = true;
+    upload.received_size = received_size;
+    upload.expires_at = env.block.height + state.config.upload_window;
+    save_session(&mut deps.storage, session, &upload)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("received_size", received_size), log("expires_at", upload.expires_at)],
+        data: None,
+    })
+}
+
+// Joins the chunks in order and writes the file exactly as Create would, then drops the session
+pub fn try_commit_upload<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    session: u64,
+    pkey: String,
+    skey: String,
+) -> StdResult<HandleResponse> {
+    let upload = owned_session(deps, &env, session)?;
+    check_open(&upload, &env)?;
+
+    if let Some(missing) = upload.received.iter().position(|received| !received) {
+        return Err(StdError::generic_err(format!("Chunk {} hasn't been uploaded", missing)));
+    }
+    if upload.received_size != upload.total_size {
+        return Err(StdError::generic_err(format!(
+            "Received {} of the {} bytes announced",
+            upload.received_size, upload.total_size
+        )));
+    }
+
+    let mut contents = String::with_capacity(upload.total_size as usize);
+    for index in 0..upload.chunk_count {
+        let chunk: String = bucket_read(CHUNK_LOCATION, &deps.storage).load(chunk_key(session, index).as_bytes())?;
+        contents.push_str(&chunk);
+    }
+
+    try_create_file(deps, env, contents, upload.path.clone(), pkey, skey)?;
+    delete_session(&mut deps.storage, session, &upload)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("path", upload.path), log("size", upload.total_size)],
+        data: None,
+    })
+}
+
+// Works on expired sessions too, so their storage can be freed
+pub fn try_abort_upload<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    session: u64,
+) -> StdResult<HandleResponse> {
+    let upload = owned_session(deps, &env, session)?;
+    delete_session(&mut deps.storage, session, &upload)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("session", session)],
+        data: None,
+    })
+}
+
+// Lets a client see which chunks still need sending after an interruption
+// Only the owner can see a session. Ids are sequential, so other callers get the same error as for a missing one.
+pub fn query_upload_session<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    session: u64,
+    behalf: &HumanAddr,
+) -> StdResult<UploadSession> {
+    match load_session(&deps.storage, session)? {
+        Some(upload) if upload.owner == behalf.as_str() => Ok(upload),
+        _ => Err(StdError::not_found("Upload session")),
+    }
+}
@@ -2,6 +2,7 @@ use crate::viewing_key::VIEWING_KEY_SIZE;
 use sha2::{Digest, Sha256};
 use std::convert::TryInto;
 use subtle::ConstantTimeEq;
+use secret_toolkit::crypto::Prng;
 
 pub fn ct_slice_compare(s1: &[u8], s2: &[u8]) -> bool {
     bool::from(s1.ct_eq(s2))
@@ -12,4 +13,8 @@ pub fn create_hashed_password(s1: &str) -> [u8; VIEWING_KEY_SIZE] {
         .as_slice()
         .try_into()
         .expect("Wrong password length")
-}
\ No newline at end of file
+}
+
+pub fn random_u64(rng: &mut Prng) -> u64 {
+    u64::from_be_bytes(rng.rand_bytes()[0..8].try_into().unwrap())
+}